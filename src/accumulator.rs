@@ -4,6 +4,11 @@
 //! measurements and compute statistical properties such as mean, variance, and
 //! Binder cumulant.
 //!
+//! Measurements are also grouped into a bounded number of bins, which are
+//! used for binning analysis, jackknife error bars and integrated
+//! autocorrelation time estimates. Accumulators can be merged and serialized,
+//! so results from independent replicas can be combined.
+//!
 //! # Example
 //!
 //! ```rust
@@ -14,52 +19,295 @@
 //! acc.collect(2.0);
 //! acc.collect(3.0);
 //!
-//! println!("Mean: {}", acc.mean());
-//! println!("Variance: {}", acc.variance());
+//! println!("Mean: {} ± {}", acc.mean(), acc.mean_error());
+//! println!("Variance: {} ± {}", acc.variance(), acc.variance_error());
 //! println!("Binder Cumulant: {}", acc.binder_cumulant());
+//! println!("Autocorrelation time: {}", acc.autocorrelation_time());
 //! ```
 
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of bins kept by an accumulator, must be even.
+const MAX_BINS: usize = 64;
+
+/// Moments of a set of measurements.
+///
+/// Moments are kept as the mean and the sums of powers of the deviations
+/// from the mean, which are updated with the streaming formulas of Welford
+/// and Pébay and stay accurate for measurements with a large mean.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct Moments {
+    count: usize,
+    mean: f64,
+    m2: f64,
+    m3: f64,
+    m4: f64,
+}
+
+impl Moments {
+    /// Add a new measurement.
+    fn collect(&mut self, value: f64) {
+        let n1 = self.count as f64;
+        self.count += 1;
+        let n = self.count as f64;
+        let delta = value - self.mean;
+        let delta_n = delta / n;
+        let delta_n2 = delta_n * delta_n;
+        let term = delta * delta_n * n1;
+        self.mean += delta_n;
+        self.m4 += term * delta_n2 * (n * n - 3.0 * n + 3.0) + 6.0 * delta_n2 * self.m2
+            - 4.0 * delta_n * self.m3;
+        self.m3 += term * delta_n * (n - 2.0) - 3.0 * delta_n * self.m2;
+        self.m2 += term;
+    }
+
+    /// Moments of the union of two sets of measurements.
+    pub fn merge(&self, other: &Moments) -> Moments {
+        if self.count == 0 {
+            return *other;
+        }
+        if other.count == 0 {
+            return *self;
+        }
+        let na = self.count as f64;
+        let nb = other.count as f64;
+        let n = na + nb;
+        let delta = other.mean - self.mean;
+        let delta2 = delta * delta;
+        Moments {
+            count: self.count + other.count,
+            mean: self.mean + delta * nb / n,
+            m2: self.m2 + other.m2 + delta2 * na * nb / n,
+            m3: self.m3
+                + other.m3
+                + delta2 * delta * na * nb * (na - nb) / (n * n)
+                + 3.0 * delta * (na * other.m2 - nb * self.m2) / n,
+            m4: self.m4
+                + other.m4
+                + delta2 * delta2 * na * nb * (na * na - na * nb + nb * nb) / (n * n * n)
+                + 6.0 * delta2 * (na * na * other.m2 + nb * nb * self.m2) / (n * n)
+                + 4.0 * delta * (na * other.m3 - nb * self.m3) / n,
+        }
+    }
+
+    /// Number of measurements.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Mean of the measurements.
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Variance of the measurements.
+    pub fn variance(&self) -> f64 {
+        self.m2 / self.count as f64
+    }
+
+    /// Binder cumulant of the measurements.
+    pub fn binder_cumulant(&self) -> f64 {
+        let n = self.count as f64;
+        let mean2 = self.mean * self.mean;
+        let second = self.m2 / n + mean2;
+        let fourth =
+            self.m4 / n + 4.0 * self.mean * self.m3 / n + 6.0 * mean2 * self.m2 / n + mean2 * mean2;
+        1.0 - fourth / (3.0 * second.powi(2))
+    }
+}
+
+/// Merge pairs of consecutive bins, returning the last bin if left alone.
+fn coarsen(bins: &mut Vec<Moments>) -> Option<Moments> {
+    let leftover = if bins.len() % 2 == 1 {
+        bins.pop()
+    } else {
+        None
+    };
+    *bins = bins.chunks(2).map(|pair| pair[0].merge(&pair[1])).collect();
+    leftover
+}
+
 /// An accumulator helps to compute statistical properties of a stream of measurements.
+///
+/// Accumulators from independent runs can be combined with `merge`, and they
+/// can be serialized to checkpoint a measurement.
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Accumulator {
-    sum: f64,
-    sum_sq: f64,
-    sum_fourth: f64,
-    count: usize,
+    total: Moments,
+    bins: Vec<Moments>,
+    current: Moments,
+    bin_size: usize,
 }
 
 impl Accumulator {
     /// Create an empty accumulator.
     pub fn new() -> Accumulator {
         Accumulator {
-            sum: 0.0,
-            sum_sq: 0.0,
-            sum_fourth: 0.0,
-            count: 0,
+            total: Moments::default(),
+            bins: Vec::with_capacity(MAX_BINS),
+            current: Moments::default(),
+            bin_size: 1,
         }
     }
 
     /// Add a new measurement to the accumulator.
     pub fn collect(&mut self, value: f64) {
-        self.sum += value;
-        self.sum_sq += value * value;
-        self.sum_fourth += value * value * value * value;
-        self.count += 1;
+        self.total.collect(value);
+        self.current.collect(value);
+        if self.current.count >= self.bin_size {
+            self.bins.push(self.current);
+            self.current = Moments::default();
+        }
+        while self.bins.len() >= MAX_BINS {
+            self.double_bin_size();
+        }
+    }
+
+    /// Combine the measurements of another accumulator into this one.
+    ///
+    /// Bins of both accumulators are brought to the same size before being
+    /// combined, measurements that do not fill a bin only count towards the
+    /// moments.
+    pub fn merge(&mut self, other: &Accumulator) {
+        let mut other = other.clone();
+        while other.bin_size < self.bin_size {
+            other.double_bin_size();
+        }
+        while self.bin_size < other.bin_size {
+            self.double_bin_size();
+        }
+        self.total = self.total.merge(&other.total);
+        self.bins.extend(other.bins);
+        self.current = self.current.merge(&other.current);
+        while self.bins.len() >= MAX_BINS {
+            self.double_bin_size();
+        }
+    }
+
+    /// Moments of all the measurements.
+    pub fn moments(&self) -> &Moments {
+        &self.total
+    }
+
+    /// Number of measurements.
+    pub fn count(&self) -> usize {
+        self.total.count()
     }
 
     /// Compute the mean of the measurements.
     pub fn mean(&self) -> f64 {
-        self.sum / self.count as f64
+        self.total.mean()
     }
 
     /// Compute the variance of the measurements.
     pub fn variance(&self) -> f64 {
-        self.sum_sq / self.count as f64 - self.mean().powi(2)
+        self.total.variance()
     }
 
     /// Compute the Binder cumulant of the measurements.
     pub fn binder_cumulant(&self) -> f64 {
-        1.0 - (self.sum_fourth / self.count as f64)
-            / (3.0 * (self.sum_sq / self.count as f64).powi(2))
+        self.total.binder_cumulant()
+    }
+
+    /// Standard error of the mean estimated from the bin means.
+    ///
+    /// Returns `NaN` when fewer than two bins have been filled.
+    pub fn mean_error(&self) -> f64 {
+        Self::binned_error(&self.bin_means())
+    }
+
+    /// Standard error of the mean at every blocking level.
+    ///
+    /// Each entry holds the block size and the error estimated with blocks of
+    /// that size. The error grows with the block size until the blocks are
+    /// longer than the autocorrelation time, where it reaches a plateau.
+    pub fn blocking(&self) -> Vec<(usize, f64)> {
+        let mut levels = Vec::new();
+        let mut means = self.bin_means();
+        let mut size = self.bin_size;
+        while means.len() >= 2 {
+            levels.push((size, Self::binned_error(&means)));
+            means = means
+                .chunks_exact(2)
+                .map(|pair| (pair[0] + pair[1]) / 2.0)
+                .collect();
+            size *= 2;
+        }
+        levels
+    }
+
+    /// Integrated autocorrelation time in units of measurements.
+    ///
+    /// Estimated from the ratio between the binned and the naive variance
+    /// of the mean, uncorrelated measurements give one half. Returns `NaN`
+    /// when fewer than two bins have been filled and one half when every
+    /// measurement is the same, as there is no correlation to measure.
+    pub fn autocorrelation_time(&self) -> f64 {
+        let means = self.bin_means();
+        if means.len() < 2 {
+            return f64::NAN;
+        }
+        let variance = self.variance();
+        if variance <= 0.0 {
+            return 0.5;
+        }
+        let nbins = means.len() as f64;
+        let mean = means.iter().sum::<f64>() / nbins;
+        let binned = means.iter().map(|m| (m - mean).powi(2)).sum::<f64>() / (nbins - 1.0);
+        0.5 * self.bin_size as f64 * binned / variance
+    }
+
+    /// Jackknife error of a quantity derived from the moments.
+    ///
+    /// Each bin is left out in turn and the spread of the derived quantity
+    /// over the reduced samples gives its error. Returns `NaN` when fewer than
+    /// two bins have been filled.
+    pub fn jackknife<F: Fn(&Moments) -> f64>(&self, quantity: F) -> f64 {
+        let nbins = self.bins.len();
+        if nbins < 2 {
+            return f64::NAN;
+        }
+        let mut prefix = vec![Moments::default(); nbins + 1];
+        let mut suffix = vec![Moments::default(); nbins + 1];
+        for i in 0..nbins {
+            prefix[i + 1] = prefix[i].merge(&self.bins[i]);
+            suffix[nbins - i - 1] = self.bins[nbins - i - 1].merge(&suffix[nbins - i]);
+        }
+        let estimates: Vec<f64> = (0..nbins)
+            .map(|i| quantity(&prefix[i].merge(&suffix[i + 1])))
+            .collect();
+        let mean = estimates.iter().sum::<f64>() / nbins as f64;
+        let spread = estimates.iter().map(|e| (e - mean).powi(2)).sum::<f64>();
+        ((nbins - 1) as f64 / nbins as f64 * spread).sqrt()
+    }
+
+    /// Jackknife error of the variance.
+    pub fn variance_error(&self) -> f64 {
+        self.jackknife(Moments::variance)
+    }
+
+    /// Jackknife error of the Binder cumulant.
+    pub fn binder_cumulant_error(&self) -> f64 {
+        self.jackknife(Moments::binder_cumulant)
+    }
+
+    /// Merge pairs of bins, folding a lone last bin into the partial bin.
+    fn double_bin_size(&mut self) {
+        if let Some(leftover) = coarsen(&mut self.bins) {
+            self.current = leftover.merge(&self.current);
+        }
+        self.bin_size *= 2;
+    }
+
+    fn bin_means(&self) -> Vec<f64> {
+        self.bins.iter().map(Moments::mean).collect()
+    }
+
+    fn binned_error(means: &[f64]) -> f64 {
+        let nbins = means.len() as f64;
+        let mean = means.iter().sum::<f64>() / nbins;
+        let spread = means.iter().map(|m| (m - mean).powi(2)).sum::<f64>();
+        (spread / (nbins * (nbins - 1.0))).sqrt()
     }
 }
 
@@ -68,3 +316,85 @@ impl Default for Accumulator {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::accumulator::Accumulator;
+
+    #[test]
+    fn uncorrelated_measurements_have_short_autocorrelation() {
+        let mut acc = Accumulator::new();
+        for i in 0..10000 {
+            acc.collect((i % 2) as f64);
+        }
+        assert!((acc.mean() - 0.5).abs() < 1e-12);
+        assert!(acc.mean_error() < 1e-3);
+        assert!(acc.autocorrelation_time() < 0.5);
+    }
+
+    #[test]
+    fn correlated_measurements_have_long_autocorrelation() {
+        let mut acc = Accumulator::new();
+        for i in 0..10000 {
+            acc.collect(((i / 500) % 2) as f64);
+        }
+        assert!(acc.autocorrelation_time() > 50.0);
+        assert!(acc.mean_error() > 0.05);
+    }
+
+    #[test]
+    fn autocorrelation_time_needs_two_bins_and_some_spread() {
+        let mut acc = Accumulator::new();
+        acc.collect(1.0);
+        assert!(acc.autocorrelation_time().is_nan());
+        for _ in 0..1000 {
+            acc.collect(1.0);
+        }
+        assert_eq!(acc.autocorrelation_time(), 0.5);
+    }
+
+    #[test]
+    fn jackknife_matches_standard_error_of_the_mean() {
+        let mut acc = Accumulator::new();
+        for i in 0..6400 {
+            acc.collect((i * 7919 % 101) as f64);
+        }
+        let jackknife = acc.jackknife(|m| m.mean());
+        assert!((jackknife - acc.mean_error()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn variance_is_stable_for_large_values() {
+        let mut acc = Accumulator::new();
+        for i in 0..1000 {
+            acc.collect(1e9 + (i % 2) as f64);
+        }
+        assert!((acc.variance() - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn binder_cumulant_of_constant_values() {
+        let mut acc = Accumulator::new();
+        for _ in 0..100 {
+            acc.collect(-2.0);
+        }
+        assert!((acc.binder_cumulant() - 2.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn merged_accumulators_match_a_single_one() {
+        let values: Vec<f64> = (0..3000).map(|i| ((i * 7919) % 101) as f64).collect();
+        let mut single = Accumulator::new();
+        values.iter().for_each(|&v| single.collect(v));
+        let mut first = Accumulator::new();
+        let mut second = Accumulator::new();
+        values[..1000].iter().for_each(|&v| first.collect(v));
+        values[1000..].iter().for_each(|&v| second.collect(v));
+        first.merge(&second);
+        assert_eq!(first.count(), single.count());
+        assert!((first.mean() - single.mean()).abs() < 1e-9);
+        assert!((first.variance() - single.variance()).abs() < 1e-9);
+        assert!((first.binder_cumulant() - single.binder_cumulant()).abs() < 1e-9);
+        assert!(first.mean_error().is_finite());
+    }
+}
@@ -0,0 +1,217 @@
+//! Post-processing of the observables written by the `ObservableSensor`.
+//!
+//! Every measurement stage is summarised from its `energy` and
+//! `magnetization` columns, with error bars from the binning and jackknife
+//! estimates of the `Accumulator`. Relaxation steps are skipped.
+//!
+//! Unlike the `StatSensor`, which reports the total energy and
+//! magnetization, energies and magnetizations are given per spin here, and
+//! the magnetization along the field is not summarised. Specific heat,
+//! susceptibility and Binder cumulant are the same in both.
+
+use crate::{
+    accumulator::Accumulator,
+    error::{IoError, IoResult},
+    output::column,
+};
+use arrow::{array::AsArray, datatypes::Float64Type, datatypes::UInt64Type};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::{collections::BTreeMap, fs::File, io::Write, path::Path};
+
+/// Summary of a measurement stage.
+///
+/// Energies and magnetizations are given per spin, and every quantity comes
+/// with its standard error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageSummary {
+    pub stage: usize,
+    pub temperature: f64,
+    pub field: f64,
+    pub n: usize,
+    pub steps: usize,
+    pub energy: f64,
+    pub energy_error: f64,
+    pub specific_heat: f64,
+    pub specific_heat_error: f64,
+    pub magnetization: f64,
+    pub magnetization_error: f64,
+    pub susceptibility: f64,
+    pub susceptibility_error: f64,
+    pub binder_cumulant: f64,
+    pub binder_cumulant_error: f64,
+}
+
+impl StageSummary {
+    /// Names of the values returned by `values`.
+    pub const COLUMNS: [&str; 15] = [
+        "stage",
+        "temperature",
+        "field",
+        "n",
+        "steps",
+        "energy",
+        "energy_error",
+        "specific_heat",
+        "specific_heat_error",
+        "magnetization",
+        "magnetization_error",
+        "susceptibility",
+        "susceptibility_error",
+        "binder_cumulant",
+        "binder_cumulant_error",
+    ];
+
+    fn new(stage: &Stage) -> Self {
+        let n = stage.n as f64;
+        let t = stage.temperature;
+        Self {
+            stage: stage.stage,
+            temperature: t,
+            field: stage.field,
+            n: stage.n,
+            steps: stage.energy.count(),
+            energy: stage.energy.mean() / n,
+            energy_error: stage.energy.mean_error() / n,
+            specific_heat: stage.energy.variance() / (n * t * t),
+            specific_heat_error: stage.energy.variance_error() / (n * t * t),
+            magnetization: stage.magnetization.mean() / n,
+            magnetization_error: stage.magnetization.mean_error() / n,
+            susceptibility: stage.magnetization.variance() / (n * t),
+            susceptibility_error: stage.magnetization.variance_error() / (n * t),
+            binder_cumulant: stage.magnetization.binder_cumulant(),
+            binder_cumulant_error: stage.magnetization.binder_cumulant_error(),
+        }
+    }
+
+    /// Values of the summary in the order of `COLUMNS`.
+    pub fn values(&self) -> [f64; 15] {
+        [
+            self.stage as f64,
+            self.temperature,
+            self.field,
+            self.n as f64,
+            self.steps as f64,
+            self.energy,
+            self.energy_error,
+            self.specific_heat,
+            self.specific_heat_error,
+            self.magnetization,
+            self.magnetization_error,
+            self.susceptibility,
+            self.susceptibility_error,
+            self.binder_cumulant,
+            self.binder_cumulant_error,
+        ]
+    }
+}
+
+/// Measurements of a single stage.
+struct Stage {
+    stage: usize,
+    temperature: f64,
+    field: f64,
+    n: usize,
+    energy: Accumulator,
+    magnetization: Accumulator,
+}
+
+/// Summarise every measurement stage of an observables parquet file.
+///
+/// The file must contain the `energy` and `magnetization` columns, which
+/// are recorded by default.
+pub fn analyze_observables<P: AsRef<Path>>(path: P) -> IoResult<Vec<StageSummary>> {
+    let file = File::open(path)?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+    let mut stages: BTreeMap<usize, Stage> = BTreeMap::new();
+    for batch in reader {
+        let batch = batch?;
+        let relax = batch
+            .column_by_name("relax")
+            .and_then(|column| column.as_boolean_opt())
+            .ok_or_else(|| IoError::MissingColumn("relax".into()))?;
+        let stage = column::<UInt64Type>(&batch, "stage")?;
+        let n = column::<UInt64Type>(&batch, "n")?;
+        let temperature = column::<Float64Type>(&batch, "temperature")?;
+        let field = column::<Float64Type>(&batch, "field")?;
+        let energy = column::<Float64Type>(&batch, "energy")?;
+        let magnetization = column::<Float64Type>(&batch, "magnetization")?;
+        for row in 0..batch.num_rows() {
+            if relax.value(row) {
+                continue;
+            }
+            let id = stage.value(row) as usize;
+            let entry = stages.entry(id).or_insert_with(|| Stage {
+                stage: id,
+                temperature: temperature.value(row),
+                field: field.value(row),
+                n: n.value(row) as usize,
+                energy: Accumulator::new(),
+                magnetization: Accumulator::new(),
+            });
+            entry.energy.collect(energy.value(row));
+            entry.magnetization.collect(magnetization.value(row));
+        }
+    }
+    Ok(stages.values().map(StageSummary::new).collect())
+}
+
+/// Write stage summaries as comma separated values with a header.
+pub fn write_csv<W: Write>(writer: &mut W, summaries: &[StageSummary]) -> IoResult<()> {
+    writeln!(writer, "{}", StageSummary::COLUMNS.join(","))?;
+    for summary in summaries {
+        let values: Vec<String> = summary.values().iter().map(|v| v.to_string()).collect();
+        writeln!(writer, "{}", values.join(","))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        output::{Metadata, ObservableParquetOutput, SummaryParquetOutput, WithMetadata},
+        state::IsingSpin,
+        thermostat::Thermostat,
+    };
+
+    #[test]
+    fn relax_steps_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("observables.parquet");
+        let columns = vec!["energy".to_string(), "magnetization".to_string()];
+        let thermostat = Thermostat::<IsingSpin>::new(2.0, Default::default());
+        {
+            let mut output = ObservableParquetOutput::try_new(&path, &columns).unwrap();
+            output.set_metadata(&Metadata::new().set("vegas.seed", 7));
+            let relax = vec![vec![100.0; 10], vec![10.0; 10]];
+            output
+                .write(true, Some(10), 0, 4, &thermostat, &relax)
+                .unwrap();
+            let energy = (0..64).map(|i| -8.0 + (i % 2) as f64 * 4.0).collect();
+            let magnetization = vec![2.0; 64];
+            output
+                .write(false, None, 1, 4, &thermostat, &[energy, magnetization])
+                .unwrap();
+        }
+        let summaries = analyze_observables(&path).unwrap();
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!((summary.stage, summary.steps, summary.n), (1, 64, 4));
+        assert!((summary.energy + 1.5).abs() < 1e-12);
+        assert!((summary.magnetization - 0.5).abs() < 1e-12);
+        assert!(summary.susceptibility.abs() < 1e-12);
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &summaries).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("stage,temperature,field,n,steps,energy"));
+        assert_eq!(csv.lines().count(), 2);
+        let summary_path = dir.path().join("summary.parquet");
+        {
+            let mut output = SummaryParquetOutput::try_new(&summary_path).unwrap();
+            output.set_metadata(&Metadata::read(&path).unwrap());
+            output.write(&summaries).unwrap();
+        }
+        let metadata = Metadata::read(&summary_path).unwrap();
+        assert_eq!(metadata.get("vegas.seed"), Some("7"));
+    }
+}
@@ -4,7 +4,8 @@
 //! site for a given state.
 //!
 //! The module provides several built-in energy components, such as
-//! `Gauge`, `UniaxialAnisotropy`, `Zeeman`, and `Exchange`.
+//! `Gauge`, `UniaxialAnisotropy`, `SingleIonAnisotropy`, `Zeeman`, and
+//! `Exchange`.
 //!
 //! It also provides a `Compound` energy component that allows you to
 //! combine multiple energy components into a single one. The compound
@@ -13,6 +14,10 @@
 //! `hamiltonian!` is provided to easily build complex Hamiltonians by
 //! combining multiple energy components.
 //!
+//! Every component has a name, and a compound reports the energy of each of
+//! its components separately through `Hamiltonian::energy_breakdown`. Use
+//! `Named` to tell apart two components of the same kind.
+//!
 //! # Example
 //!
 //! ```rust
@@ -31,7 +36,7 @@
 //! ```
 
 use crate::{
-    state::{Spin, State},
+    state::{Spin, SpinArrays, State},
     thermostat::Thermostat,
 };
 use sprs::{CsMat, TriMat};
@@ -57,6 +62,26 @@ pub trait Hamiltonian<S: Spin>: Clone {
             .map(|i| self.energy(thermostat, state, i))
             .sum()
     }
+
+    /// Name of the energy component.
+    fn name(&self) -> &str {
+        "energy"
+    }
+
+    /// Names of the components reported by `energy_breakdown`.
+    fn component_names(&self) -> Vec<String> {
+        vec![self.name().to_string()]
+    }
+
+    /// Push the total energy of every component of a state.
+    fn energy_breakdown(
+        &self,
+        thermostat: &Thermostat<S>,
+        state: &State<S>,
+        values: &mut Vec<f64>,
+    ) {
+        values.push(self.total_energy(thermostat, state));
+    }
 }
 
 /// Some constant energy that doesn't depend on the state.
@@ -77,6 +102,10 @@ impl<S: Spin> Hamiltonian<S> for Gauge {
         debug_assert!(index < state.len());
         self.value
     }
+
+    fn name(&self) -> &str {
+        "gauge"
+    }
 }
 
 /// Strong preference for a given axis.
@@ -108,7 +137,7 @@ where
     fn energy(&self, _thermostat: &Thermostat<S>, state: &State<S>, index: usize) -> f64 {
         debug_assert!(index < state.len());
         let s = state.at(index);
-        s.dot(&self.reference).powi(2) * self.strength
+        (s.dot(&self.reference) / S::length()).powi(2) * self.strength
     }
 
     fn total_energy(&self, _thermostat: &Thermostat<S>, state: &State<S>) -> f64 {
@@ -116,16 +145,56 @@ where
             .spins()
             .iter()
             .map(|s| (s.dot(&self.reference)).powi(2))
-            .sum()
+            .sum::<f64>()
+            * self.strength
+            / S::length().powi(2)
+    }
+
+    fn name(&self) -> &str {
+        "anisotropy"
+    }
+}
+
+/// Single ion anisotropy `D Sz²` of the Blume-Capel model.
+///
+/// A positive strength favors small projections along the z axis, for spin
+/// one Ising spins it favors the zero state.
+#[derive(Clone, Debug)]
+pub struct SingleIonAnisotropy {
+    strength: f64,
+}
+
+impl SingleIonAnisotropy {
+    pub fn new(d: f64) -> Self {
+        Self { strength: d }
+    }
+}
+
+impl<S> Hamiltonian<S> for SingleIonAnisotropy
+where
+    S: Spin,
+{
+    fn energy(&self, _thermostat: &Thermostat<S>, state: &State<S>, index: usize) -> f64 {
+        debug_assert!(index < state.len());
+        self.strength * state.at(index).sz().powi(2)
+    }
+
+    fn total_energy(&self, _thermostat: &Thermostat<S>, state: &State<S>) -> f64 {
+        self.strength * state.spins().iter().map(|s| s.sz().powi(2)).sum::<f64>()
+    }
+
+    fn name(&self) -> &str {
+        "single_ion"
     }
 }
 
 /// Energy resulting from a magnetic field.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct Zeeman<S>
 where
     S: Spin,
 {
+    moment: f64,
     phantom: PhantomData<S>,
 }
 
@@ -134,12 +203,30 @@ where
     S: Spin,
 {
     pub fn new() -> Self {
+        Self::with_moment(1.0)
+    }
+
+    /// Energy of spins whose moment is `moment` times their length.
+    ///
+    /// Unit spins standing in for classical spins of length `S` take a
+    /// moment of `S`.
+    pub fn with_moment(moment: f64) -> Self {
         Self {
+            moment,
             phantom: PhantomData,
         }
     }
 }
 
+impl<S> Default for Zeeman<S>
+where
+    S: Spin,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<S> Hamiltonian<S> for Zeeman<S>
 where
     S: Spin,
@@ -147,16 +234,23 @@ where
     fn energy(&self, thermostat: &Thermostat<S>, state: &State<S>, index: usize) -> f64 {
         debug_assert!(index < state.len());
         let s = state.at(index);
-        s.dot(thermostat.field().orientation()) * thermostat.field().magnitude()
+        -s.dot(thermostat.field().orientation()) * thermostat.field().value() * self.moment
+            / S::length()
     }
 
     fn total_energy(&self, thermostat: &Thermostat<S>, state: &State<S>) -> f64 {
-        -thermostat.field().magnitude()
+        -thermostat.field().value()
+            * self.moment
             * state
                 .spins()
                 .iter()
                 .map(|s| s.dot(thermostat.field().orientation()))
                 .sum::<f64>()
+            / S::length()
+    }
+
+    fn name(&self) -> &str {
+        "zeeman"
     }
 }
 
@@ -177,10 +271,8 @@ impl Exchange {
         let n_sites = lattice.sites().len();
         let mut mat = TriMat::<f64>::new((n_sites, n_sites));
         for edge in lattice.edges() {
-            if edge.source() <= edge.target() {
-                mat.add_triplet(edge.source(), edge.target(), exchange);
-                mat.add_triplet(edge.target(), edge.source(), exchange);
-            }
+            mat.add_triplet(edge.source(), edge.target(), exchange);
+            mat.add_triplet(edge.target(), edge.source(), exchange);
         }
         let matrix = mat.to_csr();
         Self::new(matrix)
@@ -211,6 +303,135 @@ where
             .fold(0f64, |s, i| s + i)
             / 2.0
     }
+
+    fn name(&self) -> &str {
+        "exchange"
+    }
+}
+
+/// Exchange interaction for lattices where every site has the same number
+/// of neighbors.
+///
+/// Neighbors are stored in a flat table with `coordination` entries per
+/// site, so both energies are tight loops over slices instead of sparse
+/// matrix rows. The same energies can be computed over a `SpinArrays`
+/// copy of the state, which stores the projections of the spins per axis.
+#[derive(Clone, Debug)]
+pub struct FixedExchange {
+    exchange: f64,
+    coordination: usize,
+    neighbors: Vec<usize>,
+}
+
+impl FixedExchange {
+    /// Create a new exchange energy from a flat neighbor table.
+    pub fn new(exchange: f64, coordination: usize, neighbors: Vec<usize>) -> Self {
+        debug_assert!(coordination > 0 && neighbors.len().is_multiple_of(coordination));
+        Self {
+            exchange,
+            coordination,
+            neighbors,
+        }
+    }
+
+    /// Create a new exchange energy from a lattice.
+    ///
+    /// Returns `None` when the sites of the lattice don't all have the same
+    /// number of neighbors.
+    pub fn from_lattice(exchange: f64, lattice: &Lattice) -> Option<Self> {
+        let mut neighbor_list = vec![Vec::new(); lattice.sites().len()];
+        for edge in lattice.edges() {
+            neighbor_list[edge.source()].push(edge.target());
+            neighbor_list[edge.target()].push(edge.source());
+        }
+        let coordination = neighbor_list.first().map(Vec::len)?;
+        if coordination == 0 || neighbor_list.iter().any(|n| n.len() != coordination) {
+            return None;
+        }
+        Some(Self::new(
+            exchange,
+            coordination,
+            neighbor_list.into_iter().flatten().collect(),
+        ))
+    }
+
+    /// Number of neighbors of every site.
+    pub fn coordination(&self) -> usize {
+        self.coordination
+    }
+
+    #[inline]
+    fn neighbors_of(&self, index: usize) -> &[usize] {
+        &self.neighbors[index * self.coordination..(index + 1) * self.coordination]
+    }
+
+    /// Sum of the projections of the neighbors of a site, per axis.
+    #[inline]
+    fn local_field(&self, spins: &SpinArrays, index: usize) -> [f64; 3] {
+        let mut field = [0.0; 3];
+        for &neighbor in self.neighbors_of(index) {
+            field[0] += spins.x[neighbor];
+            field[1] += spins.y[neighbor];
+            field[2] += spins.z[neighbor];
+        }
+        field
+    }
+
+    /// Exchange energy of a site, read from the per axis spin arrays.
+    ///
+    /// The neighbors are summed per axis first, so the site takes a single
+    /// dot product with its local exchange field.
+    pub fn site_energy_of(&self, spins: &SpinArrays, index: usize) -> f64 {
+        debug_assert!(index < spins.len());
+        let [hx, hy, hz] = self.local_field(spins, index);
+        -self.exchange * (spins.x[index] * hx + spins.y[index] * hy + spins.z[index] * hz)
+    }
+
+    /// Total exchange energy, read from the per axis spin arrays.
+    pub fn total_energy_of(&self, spins: &SpinArrays) -> f64 {
+        debug_assert!(spins.len() * self.coordination == self.neighbors.len());
+        let sum: f64 = (0..spins.len())
+            .map(|index| {
+                let [hx, hy, hz] = self.local_field(spins, index);
+                spins.x[index] * hx + spins.y[index] * hy + spins.z[index] * hz
+            })
+            .sum();
+        -self.exchange * sum / 2.0
+    }
+}
+
+impl<S> Hamiltonian<S> for FixedExchange
+where
+    S: Spin,
+{
+    fn energy(&self, _thermostat: &Thermostat<S>, state: &State<S>, index: usize) -> f64 {
+        debug_assert!(index < state.len());
+        let site = state.at(index);
+        let mut sum = 0.0;
+        for &neighbor in self.neighbors_of(index) {
+            sum += site.dot(state.at(neighbor));
+        }
+        -self.exchange * sum
+    }
+
+    fn total_energy(&self, _thermostat: &Thermostat<S>, state: &State<S>) -> f64 {
+        debug_assert!(state.len() * self.coordination == self.neighbors.len());
+        let spins = state.spins();
+        let mut sum = 0.0;
+        for (site, neighbors) in spins
+            .iter()
+            .zip(self.neighbors.chunks_exact(self.coordination))
+        {
+            for &neighbor in neighbors {
+                sum += site.dot(&spins[neighbor]);
+            }
+        }
+        -self.exchange * sum / 2.0
+    }
+
+    fn name(&self) -> &str {
+        "exchange"
+    }
 }
 
 /// A compound energy is the sum of two energy components.
@@ -254,6 +475,65 @@ where
     fn energy(&self, thermostat: &Thermostat<S>, state: &State<S>, index: usize) -> f64 {
         self.a.energy(thermostat, state, index) + self.b.energy(thermostat, state, index)
     }
+
+    fn total_energy(&self, thermostat: &Thermostat<S>, state: &State<S>) -> f64 {
+        self.a.total_energy(thermostat, state) + self.b.total_energy(thermostat, state)
+    }
+
+    fn name(&self) -> &str {
+        "compound"
+    }
+
+    fn component_names(&self) -> Vec<String> {
+        let mut names = self.a.component_names();
+        names.extend(self.b.component_names());
+        names
+    }
+
+    fn energy_breakdown(
+        &self,
+        thermostat: &Thermostat<S>,
+        state: &State<S>,
+        values: &mut Vec<f64>,
+    ) {
+        self.a.energy_breakdown(thermostat, state, values);
+        self.b.energy_breakdown(thermostat, state, values);
+    }
+}
+
+/// An energy component reported under a custom name.
+#[derive(Clone, Debug)]
+pub struct Named<H> {
+    name: String,
+    hamiltonian: H,
+}
+
+impl<H> Named<H> {
+    /// Give a name to an energy component.
+    pub fn new(name: &str, hamiltonian: H) -> Self {
+        Self {
+            name: name.to_string(),
+            hamiltonian,
+        }
+    }
+}
+
+impl<S, H> Hamiltonian<S> for Named<H>
+where
+    S: Spin,
+    H: Hamiltonian<S>,
+{
+    fn energy(&self, thermostat: &Thermostat<S>, state: &State<S>, index: usize) -> f64 {
+        self.hamiltonian.energy(thermostat, state, index)
+    }
+
+    fn total_energy(&self, thermostat: &Thermostat<S>, state: &State<S>) -> f64 {
+        self.hamiltonian.total_energy(thermostat, state)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 /// A macro to easily build complex hamiltonians.
@@ -292,8 +572,11 @@ macro_rules! hamiltonian {
 #[cfg(test)]
 mod tests {
     use crate::{
-        energy::{Compound, Gauge, Hamiltonian, UniaxialAnisotropy, Zeeman},
-        state::{Field, HeisenbergSpin, Spin, State},
+        energy::{
+            Compound, Exchange, FixedExchange, Gauge, Hamiltonian, Named, SingleIonAnisotropy,
+            UniaxialAnisotropy, Zeeman,
+        },
+        state::{BlumeCapelSpin, Field, HeisenbergSpin, HeisenbergSpinS, Spin, SpinArrays, State},
         thermostat::Thermostat,
     };
 
@@ -355,6 +638,24 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_zeeman_energy_with_negative_field() {
+        let ups = State::<HeisenbergSpin>::up_with_size(10);
+        let zeeman = Zeeman::new();
+        let thermostat = Thermostat::new(0.0, Field::new(HeisenbergSpin::up(), -1.0));
+        assert!((zeeman.total_energy(&thermostat, &ups) - 10.0).abs() < 1e-12);
+        assert!((zeeman.energy(&thermostat, &ups, 0) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn zeeman_energy_scales_with_the_moment() {
+        let ups = State::<HeisenbergSpin>::up_with_size(10);
+        let zeeman = Zeeman::with_moment(1.3);
+        let thermostat = Thermostat::new(0.0, Field::new(HeisenbergSpin::up(), 2.0));
+        assert!((zeeman.total_energy(&thermostat, &ups) + 26.0).abs() < 1e-12);
+        assert!((zeeman.energy(&thermostat, &ups, 0) + 2.6).abs() < 1e-12);
+    }
+
     #[test]
     fn lets_try_a_simple_composition() {
         let ups = State::<HeisenbergSpin>::up_with_size(10);
@@ -373,4 +674,85 @@ mod tests {
         );
         assert!(hamiltonian.total_energy(&Thermostat::near_zero(), &state) - 200.0 < 1e-12);
     }
+
+    #[test]
+    fn compound_reports_each_component() {
+        let state = State::<HeisenbergSpin>::up_with_size(10);
+        let thermostat = Thermostat::new(1.0, Field::new(HeisenbergSpin::up(), 2.0));
+        let hamiltonian = hamiltonian!(
+            UniaxialAnisotropy::new(HeisenbergSpin::up(), 0.5),
+            Named::new("offset", Gauge::new(1.0)),
+            Zeeman::new()
+        );
+        assert_eq!(
+            hamiltonian.component_names(),
+            vec!["anisotropy", "offset", "zeeman"]
+        );
+        let mut values = Vec::new();
+        hamiltonian.energy_breakdown(&thermostat, &state, &mut values);
+        assert_eq!(values, vec![5.0, 10.0, -20.0]);
+        assert!((hamiltonian.total_energy(&thermostat, &state) + 5.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn fixed_exchange_matches_sparse_exchange() {
+        let lattice = vegas_lattice::Lattice::bcc(1.0).expand(3, 3, 3);
+        let state =
+            State::<HeisenbergSpin>::rand_with_size(&mut rand::rng(), lattice.sites().len());
+        let thermostat = Thermostat::near_zero();
+        let sparse = Exchange::from_lattice(1.5, &lattice);
+        let fixed = FixedExchange::from_lattice(1.5, &lattice).unwrap();
+        assert_eq!(fixed.coordination(), 8);
+        for index in 0..state.len() {
+            let expected = sparse.energy(&thermostat, &state, index);
+            assert!((fixed.energy(&thermostat, &state, index) - expected).abs() < 1e-12);
+        }
+        let expected = sparse.total_energy(&thermostat, &state);
+        assert!((fixed.total_energy(&thermostat, &state) - expected).abs() < 1e-9);
+        let open = vegas_lattice::Lattice::sc(1.0).expand(3, 3, 3).drop_x();
+        assert!(FixedExchange::from_lattice(1.0, &open).is_none());
+    }
+
+    #[test]
+    fn spin_arrays_give_the_same_exchange_energies() {
+        let lattice = vegas_lattice::Lattice::sc(1.0).expand(4, 4, 4);
+        let mut state =
+            State::<HeisenbergSpin>::rand_with_size(&mut rand::rng(), lattice.sites().len());
+        let thermostat = Thermostat::near_zero();
+        let fixed = FixedExchange::from_lattice(1.5, &lattice).unwrap();
+        let mut arrays = SpinArrays::from_state(&state);
+        state.set_at(5, HeisenbergSpin::up());
+        arrays.set_at(5, state.at(5));
+        for index in 0..state.len() {
+            let expected = fixed.energy(&thermostat, &state, index);
+            assert!((fixed.site_energy_of(&arrays, index) - expected).abs() < 1e-12);
+        }
+        let expected = fixed.total_energy(&thermostat, &state);
+        assert!((fixed.total_energy_of(&arrays) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn exchange_includes_periodic_bonds() {
+        let lattice = vegas_lattice::Lattice::sc(1.0).expand(4, 4, 1).drop_z();
+        let state = State::<HeisenbergSpin>::up_with_size(lattice.sites().len());
+        let exchange = Exchange::from_lattice(1.0, &lattice);
+        let thermostat = Thermostat::near_zero();
+        assert!((exchange.total_energy(&thermostat, &state) + 32.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn field_terms_scale_with_spin_length() {
+        let state = State::<BlumeCapelSpin>::up_with_size(10);
+        let thermostat = Thermostat::new(1.0, Field::along([0.0, 0.0, 1.0], 2.0));
+        let zeeman = Zeeman::new();
+        assert!((zeeman.total_energy(&thermostat, &state) + 20.0).abs() < 1e-12);
+        assert!((zeeman.energy(&thermostat, &state, 0) + 2.0).abs() < 1e-12);
+        let anisotropy = SingleIonAnisotropy::new(0.5);
+        assert!((anisotropy.total_energy(&thermostat, &state) - 5.0).abs() < 1e-12);
+        let state = State::<HeisenbergSpinS<3>>::up_with_size(4);
+        let thermostat = Thermostat::new(1.0, Field::along([0.0, 0.0, 1.0], 1.0));
+        assert!((Zeeman::new().total_energy(&thermostat, &state) + 6.0).abs() < 1e-12);
+        assert!((state.magnetization().magnitude() - 6.0).abs() < 1e-12);
+        assert!((thermostat.field().project(&state.magnetization()) - 6.0).abs() < 1e-12);
+    }
 }
@@ -22,6 +22,21 @@ pub enum VegasError {
     TomlDeserializeError(#[from] TomlDeserializeError),
     #[error("toml serialization error: {0}")]
     TomlSerializeError(#[from] TomlSerializeError),
+    #[error("unknown observable: {0}")]
+    UnknownObservable(String),
+    #[error("observable listed twice: {0}")]
+    DuplicateObservable(String),
+    #[error("unsupported number of states: {0}, use 2 to 8")]
+    UnsupportedStates(usize),
+    #[error(
+        "unsupported spin length: {0}, Ising spins take a multiple of 1/2 up to 7/2 and \
+         Heisenberg spins any positive length"
+    )]
+    UnsupportedSpin(f64),
+    #[error("{0} is not supported by the {1} model")]
+    UnsupportedParameter(&'static str, String),
+    #[error("state has {found} spins but the sample has {expected} sites")]
+    StateSizeMismatch { expected: usize, found: usize },
     #[error("not implemented error")]
     NotImplementedError,
 }
@@ -37,12 +52,22 @@ pub enum ProgramError {
     ZeroTemperature,
     #[error("cooling rate must be greater than zero")]
     ZeroCoolRate,
+    #[error("cooling rate must be less than one for geometric spacing")]
+    CoolRateTooLarge,
     #[error("maximum field must be greater than zero")]
     ZeroField,
     #[error("field step must be greater than zero")]
     ZeroFieldStep,
+    #[error("maximum field must be greater than minimum field")]
+    FieldMaxLessThanMin,
+    #[error("field direction must have a non-zero projection on the spins")]
+    ZeroDirection,
+    #[error("invalid schedule: {0}")]
+    InvalidSchedule(String),
     #[error("machine error: {0}")]
     MachineError(#[from] MachineError),
+    #[error("io error: {0}")]
+    IoError(#[from] IoError),
 }
 
 // Error type for IO operations
@@ -54,6 +79,16 @@ pub enum IoError {
     ParquetError(#[from] ParquetError),
     #[error("arrow error: {0}")]
     ArrowError(#[from] ArrowError),
+    #[error("missing or mistyped column: {0}")]
+    MissingColumn(String),
+    #[error("duplicate column: {0}")]
+    DuplicateColumn(String),
+    #[error("no snapshot found in {0}")]
+    SnapshotNotFound(String),
+    #[error("unsupported trajectory precision: {0} bits, expected 1 to 32")]
+    UnsupportedPrecision(u8),
+    #[error("bad trajectory file: {0}")]
+    BadTrajectory(String),
 }
 
 // Error type for machine operations
@@ -61,6 +96,8 @@ pub enum IoError {
 pub enum MachineError {
     #[error("instrument error: {0}")]
     InstrumentError(#[from] InstrumentError),
+    #[error("running totals drifted by {drift} after {step} steps")]
+    TotalsDrift { step: usize, drift: f64 },
 }
 
 // Error type for instrument operations
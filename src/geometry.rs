@@ -0,0 +1,288 @@
+//! Geometry of the simulated sample.
+//!
+//! The spin state only knows about site indices, instruments that need to
+//! know where a site sits in space, like correlation functions, use the
+//! [`Geometry`] built from the lattice.
+
+use std::f64::consts::PI;
+use vegas_lattice::Lattice;
+
+/// Site positions and periodic box of a sample.
+#[derive(Debug, Clone)]
+pub struct Geometry {
+    positions: Vec<[f64; 3]>,
+    size: [f64; 3],
+    periodic: [bool; 3],
+}
+
+impl Geometry {
+    /// Create a geometry from positions, box size and periodicity.
+    pub fn new(positions: Vec<[f64; 3]>, size: [f64; 3], periodic: [bool; 3]) -> Self {
+        Self {
+            positions,
+            size,
+            periodic,
+        }
+    }
+
+    /// Create a geometry from a lattice.
+    ///
+    /// An axis is periodic when at least one edge wraps around it.
+    pub fn from_lattice(lattice: &Lattice) -> Self {
+        let positions = lattice
+            .sites()
+            .iter()
+            .map(|site| {
+                let (x, y, z) = site.position();
+                [x, y, z]
+            })
+            .collect();
+        let (sx, sy, sz) = lattice.size();
+        let mut periodic = [false; 3];
+        for edge in lattice.edges() {
+            let (dx, dy, dz) = edge.delta();
+            periodic[0] |= dx != 0;
+            periodic[1] |= dy != 0;
+            periodic[2] |= dz != 0;
+        }
+        Self::new(positions, [sx, sy, sz], periodic)
+    }
+
+    /// Number of sites.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Whether the geometry has no sites.
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Positions of the sites.
+    pub fn positions(&self) -> &[[f64; 3]] {
+        &self.positions
+    }
+
+    /// Size of the periodic box.
+    pub fn size(&self) -> [f64; 3] {
+        self.size
+    }
+
+    /// Periodicity along each axis.
+    pub fn periodic(&self) -> [bool; 3] {
+        self.periodic
+    }
+
+    /// Displacement from site `i` to site `j` using the minimum image along
+    /// periodic axes.
+    pub fn displacement(&self, i: usize, j: usize) -> [f64; 3] {
+        let mut delta = [0.0; 3];
+        for (axis, d) in delta.iter_mut().enumerate() {
+            *d = self.positions[j][axis] - self.positions[i][axis];
+            if self.periodic[axis] && self.size[axis] > 0.0 {
+                *d -= self.size[axis] * (*d / self.size[axis]).round();
+            }
+        }
+        delta
+    }
+
+    /// Minimum image distance between sites `i` and `j`.
+    pub fn distance(&self, i: usize, j: usize) -> f64 {
+        let [dx, dy, dz] = self.displacement(i, j);
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
+}
+
+/// Triangulate a two dimensional lattice in the xy plane.
+///
+/// The faces of the lattice graph are found by walking around every site
+/// in angular order using the in-plane displacement of each edge, so
+/// periodic edges are handled naturally. Every face with positive area is
+/// split into a fan of counter-clockwise triangles, which drops the outer
+/// face of open samples. Edges without an in-plane displacement are
+/// ignored, the lattice must be a single planar layer.
+pub fn triangulate(lattice: &Lattice) -> Vec<[usize; 3]> {
+    let (lx, ly, _) = lattice.size();
+    let position = |i: usize| lattice.sites()[i].position();
+
+    // Half edges as (source, target, displacement), twins are adjacent.
+    let mut half_edges: Vec<(usize, usize, [f64; 2])> = Vec::new();
+    for edge in lattice.edges() {
+        let (sx, sy, _) = position(edge.source());
+        let (tx, ty, _) = position(edge.target());
+        let (dx, dy, _) = edge.delta();
+        let displacement = [tx + dx as f64 * lx - sx, ty + dy as f64 * ly - sy];
+        if displacement[0].hypot(displacement[1]) < 1e-12 {
+            continue;
+        }
+        half_edges.push((edge.source(), edge.target(), displacement));
+        half_edges.push((
+            edge.target(),
+            edge.source(),
+            [-displacement[0], -displacement[1]],
+        ));
+    }
+
+    // Outgoing half edges of every site sorted counter-clockwise.
+    let mut outgoing = vec![Vec::new(); lattice.sites().len()];
+    for (index, &(source, _, _)) in half_edges.iter().enumerate() {
+        outgoing[source].push(index);
+    }
+    let angle = |index: usize| {
+        let [dx, dy] = half_edges[index].2;
+        dy.atan2(dx).rem_euclid(2.0 * PI)
+    };
+    let mut rank = vec![0; half_edges.len()];
+    for around in outgoing.iter_mut() {
+        around.sort_by(|&a, &b| angle(a).total_cmp(&angle(b)));
+        for (k, &index) in around.iter().enumerate() {
+            rank[index] = k;
+        }
+    }
+
+    // The face to the left of a half edge continues with the half edge
+    // right before its twin in counter-clockwise order.
+    let next = |index: usize| {
+        let twin = index ^ 1;
+        let around = &outgoing[half_edges[twin].0];
+        around[(rank[twin] + around.len() - 1) % around.len()]
+    };
+
+    let mut visited = vec![false; half_edges.len()];
+    let mut triangles = Vec::new();
+    for start in 0..half_edges.len() {
+        if visited[start] {
+            continue;
+        }
+        let mut face = Vec::new();
+        let mut corner = [0.0, 0.0];
+        let mut corners = Vec::new();
+        let mut current = start;
+        while !visited[current] {
+            visited[current] = true;
+            let (source, _, [dx, dy]) = half_edges[current];
+            face.push(source);
+            corners.push(corner);
+            corner = [corner[0] + dx, corner[1] + dy];
+            current = next(current);
+        }
+        let area: f64 = (0..corners.len())
+            .map(|k| {
+                let [x0, y0] = corners[k];
+                let [x1, y1] = corners[(k + 1) % corners.len()];
+                x0 * y1 - x1 * y0
+            })
+            .sum();
+        if current != start || area <= 1e-12 {
+            continue;
+        }
+        for k in 1..face.len() - 1 {
+            triangles.push([face[0], face[k], face[k + 1]]);
+        }
+    }
+    triangles
+}
+
+/// Wave vectors on a regular grid covering `[0, 1)` along each axis.
+///
+/// Wave vectors are given in units of `2π / a`, an axis with zero or one
+/// division only samples zero.
+pub fn q_grid(divisions: [usize; 3]) -> Vec<[f64; 3]> {
+    let axis =
+        |n: usize| -> Vec<f64> { (0..n.max(1)).map(|k| k as f64 / n.max(1) as f64).collect() };
+    let (xs, ys, zs) = (axis(divisions[0]), axis(divisions[1]), axis(divisions[2]));
+    let mut q = Vec::with_capacity(xs.len() * ys.len() * zs.len());
+    for &qx in &xs {
+        for &qy in &ys {
+            for &qz in &zs {
+                q.push([qx, qy, qz]);
+            }
+        }
+    }
+    q
+}
+
+/// Wave vectors along a path through the given points.
+///
+/// Every segment between consecutive points is split into `divisions`
+/// intervals, both ends of the path are included.
+pub fn q_path(points: &[[f64; 3]], divisions: usize) -> Vec<[f64; 3]> {
+    let divisions = divisions.max(1);
+    let mut q = Vec::new();
+    for pair in points.windows(2) {
+        let [from, to] = [pair[0], pair[1]];
+        for k in 0..divisions {
+            let t = k as f64 / divisions as f64;
+            q.push([
+                from[0] + t * (to[0] - from[0]),
+                from[1] + t * (to[1] - from[1]),
+                from[2] + t * (to[2] - from[2]),
+            ]);
+        }
+    }
+    if let Some(last) = points.last() {
+        q.push(*last);
+    }
+    q
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{HeisenbergSpin, Spin, State};
+
+    #[test]
+    fn test_minimum_image_distance() {
+        let lattice = Lattice::sc(1.0).expand(4, 4, 1).drop_z();
+        let geometry = Geometry::from_lattice(&lattice);
+        assert_eq!(geometry.len(), 16);
+        assert_eq!(geometry.periodic(), [true, true, false]);
+        let far = (0..geometry.len())
+            .find(|&j| geometry.positions()[j] == [3.0, 0.0, 0.0])
+            .unwrap();
+        assert!((geometry.distance(0, far) - 1.0).abs() < 1e-12);
+        assert_eq!(geometry.displacement(0, far), [-1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_triangulate_square_lattice() {
+        let periodic = Lattice::sc(1.0).expand(4, 4, 1).drop_z();
+        assert_eq!(triangulate(&periodic).len(), 32);
+        let open = Lattice::sc(1.0).expand(3, 3, 1).drop_all();
+        assert_eq!(triangulate(&open).len(), 8);
+    }
+
+    #[test]
+    fn test_skyrmion_has_unit_charge() {
+        let lattice = Lattice::sc(1.0).expand(20, 20, 1).drop_z();
+        let state: State<HeisenbergSpin> = lattice
+            .sites()
+            .iter()
+            .map(|site| {
+                let (x, y, _) = site.position();
+                let (dx, dy) = (x - 9.5, y - 9.5);
+                let theta = PI * (-(dx * dx + dy * dy).sqrt() / 3.0).exp();
+                let phi = dy.atan2(dx);
+                let (sx, sy, sz) = (
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                HeisenbergSpin::from_projections(sx, sy, sz)
+                    .orientation()
+                    .clone()
+            })
+            .collect();
+        let charge = state.topological_charge(&triangulate(&lattice));
+        assert!((charge.abs() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_q_path_includes_both_ends() {
+        let q = q_path(&[[0.0, 0.0, 0.0], [0.5, 0.0, 0.0], [0.5, 0.5, 0.0]], 2);
+        assert_eq!(q.len(), 5);
+        assert_eq!(q[1], [0.25, 0.0, 0.0]);
+        assert_eq!(q[4], [0.5, 0.5, 0.0]);
+        assert_eq!(q_grid([2, 2, 1]).len(), 4);
+    }
+}
@@ -1,21 +1,60 @@
 //! Input structures for various simulations.
 
 use crate::{
-    energy::{Exchange, Hamiltonian, Zeeman},
-    error::{VegasError, VegasResult},
-    instrument::{Instrument, ObservableSensor, StatSensor, StateSensor},
+    energy::{Compound, Exchange, Hamiltonian, SingleIonAnisotropy, Zeeman},
+    error::{IoError, VegasError, VegasResult},
+    geometry::{Geometry, q_grid, q_path},
+    instrument::{
+        CorrelationSensor, Instrument, ObservableSensor, SnapshotSensor, StatFormat, StatSensor,
+        StateSensor, TrajectorySensor,
+    },
     integrator::{Integrator, MetropolisFlipIntegrator, MetropolisIntegrator, WolffIntegrator},
     machine::Machine,
-    program::{CoolDown, HysteresisLoop, Program, Relax},
-    state::{Field, HeisenbergSpin, IsingSpin, Spin, State},
+    observable::{self, NAMES},
+    output::{GeometryParquetOutput, Metadata, StateParquetReader, WithMetadata},
+    program::{CoolDown, Forc, HysteresisLoop, Program, Relax, Schedule},
+    snapshot::SnapshotFormat,
+    state::{
+        ClockSpin, Field, HeisenbergSpin, IsingSpin, IsingSpinS, PottsSpin, Spin, State, XYSpin,
+    },
     thermostat::Thermostat,
+    trajectory::{TrajectoryEncoding, TrajectoryReader},
 };
 use clap::ValueEnum;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::{io::stdout, path::PathBuf};
+use std::{
+    collections::VecDeque,
+    f64::consts::PI,
+    fs::File,
+    io::{BufWriter, Write, stdout},
+    path::PathBuf,
+};
 use vegas_lattice::Lattice;
 
+/// Match a const generic parameter against the values a spin type is
+/// built for, running `$run` with `$alias` naming the matching type.
+macro_rules! match_spin {
+    ($value:expr, $spin:ident<$($n:literal),+> as $alias:ident => $run:expr, _ => $otherwise:expr) => {
+        match $value {
+            $(
+                $n => {
+                    type $alias = $spin<$n>;
+                    $run
+                }
+            )+
+            _ => $otherwise,
+        }
+    };
+}
+
+/// Match the number of states of the Potts and clock models.
+macro_rules! match_states {
+    ($value:expr, $spin:ident as $alias:ident => $run:expr, _ => $otherwise:expr) => {
+        match_spin!($value, $spin<2, 3, 4, 5, 6, 7, 8> as $alias => $run, _ => $otherwise)
+    };
+}
+
 #[derive(Debug, Default, Clone, ValueEnum, Serialize, Deserialize)]
 pub enum Model {
     /// Ising model
@@ -23,6 +62,26 @@ pub enum Model {
     Ising,
     /// Heisenberg model
     Heisenberg,
+    /// Planar XY model
+    #[serde(alias = "XY")]
+    Xy,
+    /// Q state Potts model
+    Potts,
+    /// Q state clock model
+    Clock,
+    /// Spin one Ising model with single ion anisotropy
+    BlumeCapel,
+}
+
+impl Model {
+    /// Number of states used by discrete models when none is given.
+    fn default_states(&self) -> usize {
+        match self {
+            Model::Potts => 3,
+            Model::Clock => 6,
+            _ => 2,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, ValueEnum, Serialize, Deserialize)]
@@ -94,6 +153,119 @@ impl Default for PeriodicBoundaryConditions {
     }
 }
 
+/// Initial spin configuration of the sample.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(tag = "kind")]
+pub enum InitialState {
+    /// Every spin up
+    Up,
+    /// Every spin down
+    Down,
+    /// Random spins
+    #[default]
+    Random,
+    /// Alternating up and down spins on the two sublattices of the sample
+    Neel,
+    /// Spins rotating from up at the origin, in the xy plane for planar spins
+    /// and in the xz plane otherwise
+    Spiral {
+        /// Wave vector in units of 2π/a
+        q: [f64; 3],
+    },
+    /// Snapshot written by a state or trajectory output
+    File {
+        /// Path to the state parquet file, or to a `.trj` trajectory file
+        path: PathBuf,
+        /// Stage of the snapshot, the last one if omitted
+        stage: Option<usize>,
+        /// Step of the snapshot, the last one of the stage if omitted
+        step: Option<usize>,
+    },
+}
+
+impl InitialState {
+    /// Build the initial state of the given lattice.
+    pub fn build<S: Spin, R: Rng>(&self, rng: &mut R, lattice: &Lattice) -> VegasResult<State<S>> {
+        let n = lattice.sites().len();
+        let state = match self {
+            InitialState::Up => State::up_with_size(n),
+            InitialState::Down => State::down_with_size(n),
+            InitialState::Random => State::rand_with_size(rng, n),
+            InitialState::Neel => sublattices(lattice)
+                .into_iter()
+                .map(|odd| if odd { S::down() } else { S::up() })
+                .collect(),
+            InitialState::Spiral { q } => {
+                let planar = S::up().sz().abs() < f64::EPSILON;
+                lattice
+                    .sites()
+                    .iter()
+                    .map(|site| {
+                        let (x, y, z) = site.position();
+                        let phase = 2.0 * PI * (q[0] * x + q[1] * y + q[2] * z);
+                        let length = S::length();
+                        if planar {
+                            S::closest(length * phase.cos(), length * phase.sin(), 0.0)
+                        } else {
+                            S::closest(length * phase.sin(), 0.0, length * phase.cos())
+                        }
+                    })
+                    .collect()
+            }
+            InitialState::File { path, stage, step } => {
+                let snapshot = if path.extension().is_some_and(|ext| ext == "trj") {
+                    TrajectoryReader::try_new(path)?.find(*stage, *step)?
+                } else {
+                    StateParquetReader::try_new(path)?.find(*stage, *step)?
+                };
+                let snapshot = snapshot
+                    .ok_or_else(|| IoError::SnapshotNotFound(path.display().to_string()))?;
+                if snapshot.state.len() != n {
+                    return Err(VegasError::StateSizeMismatch {
+                        expected: n,
+                        found: snapshot.state.len(),
+                    });
+                }
+                snapshot.state
+            }
+        };
+        Ok(state)
+    }
+}
+
+/// Two color the sites of a lattice by walking its bonds breadth first.
+///
+/// Sites at an odd number of bonds from the first site of their connected
+/// component are marked. Frustrated lattices, like the triangular one, can't
+/// be colored exactly and keep the first color found for each site.
+fn sublattices(lattice: &Lattice) -> Vec<bool> {
+    let n = lattice.sites().len();
+    let mut neighbors = vec![Vec::new(); n];
+    for edge in lattice.edges() {
+        neighbors[edge.source()].push(edge.target());
+        neighbors[edge.target()].push(edge.source());
+    }
+    let mut color: Vec<Option<bool>> = vec![None; n];
+    let mut queue = VecDeque::new();
+    for start in 0..n {
+        if color[start].is_some() {
+            continue;
+        }
+        color[start] = Some(false);
+        queue.push_back(start);
+        while let Some(site) = queue.pop_front() {
+            let odd = color[site] == Some(true);
+            for &neighbor in &neighbors[site] {
+                if color[neighbor].is_none() {
+                    color[neighbor] = Some(!odd);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+    }
+    color.into_iter().map(|c| c.unwrap_or(false)).collect()
+}
+
 /// Sample to simulate.
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Sample {
@@ -103,6 +275,9 @@ pub struct Sample {
     pub size: UnitCellSize,
     /// Periodic boundary conditions
     pub pbc: PeriodicBoundaryConditions,
+    /// Initial spin configuration
+    #[serde(default)]
+    pub initial: InitialState,
 }
 
 /// State output for a simulation.
@@ -110,27 +285,130 @@ pub struct Sample {
 pub struct StateOutput {
     /// Write the states to a parquet file
     pub path: PathBuf,
-    /// Frequency of writing states
+    /// Frequency of writing states, zero writes the first step of every
+    /// stage only
+    pub frequency: usize,
+}
+
+/// Indexed binary trajectory output.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TrajectoryOutput {
+    /// Write the frames to this file
+    pub path: PathBuf,
+    /// Frequency of writing frames, zero writes the first step of every
+    /// stage only
+    pub frequency: usize,
+    /// Bits per spin component, 16 by default, Ising spins always take one
+    /// bit each
+    pub precision: Option<u8>,
+}
+
+/// Snapshot output for visualization tools.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SnapshotOutput {
+    /// Write the snapshots to this file, VTK frames are numbered after it
+    pub path: PathBuf,
+    /// Format of the snapshots
+    #[serde(default)]
+    pub format: SnapshotFormat,
+    /// Frequency of writing snapshots, zero writes the first step of every
+    /// stage only
     pub frequency: usize,
 }
 
+/// Path through reciprocal space.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct QPath {
+    /// Corners of the path in units of 2π/a
+    pub points: Vec<[f64; 3]>,
+    /// Number of intervals between consecutive corners
+    pub divisions: usize,
+}
+
+/// Correlation output for a simulation.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CorrelationOutput {
+    /// Write the structure factor to a parquet file
+    pub structure_factor: Option<PathBuf>,
+    /// Write the pair correlation to a parquet file
+    pub pair_correlation: Option<PathBuf>,
+    /// Frequency of sampling correlations
+    pub frequency: usize,
+    /// Explicit wave vectors in units of 2π/a
+    #[serde(default)]
+    pub q: Vec<[f64; 3]>,
+    /// Regular grid of wave vectors
+    pub q_grid: Option<[usize; 3]>,
+    /// Path of wave vectors
+    pub q_path: Option<QPath>,
+}
+
+impl CorrelationOutput {
+    /// Wave vectors to sample.
+    pub fn wave_vectors(&self) -> Vec<[f64; 3]> {
+        let mut q = self.q.clone();
+        if let Some(divisions) = self.q_grid {
+            q.extend(q_grid(divisions));
+        }
+        if let Some(path) = &self.q_path {
+            q.extend(q_path(&path.points, path.divisions));
+        }
+        q
+    }
+}
+
+/// Statistics output for a simulation.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct StatOutput {
+    /// Write the statistics to the given file, stdout if missing
+    pub path: Option<PathBuf>,
+    /// Format of the statistics
+    #[serde(default)]
+    pub format: StatFormat,
+    /// Don't write statistics at all
+    #[serde(default)]
+    pub quiet: bool,
+}
+
 /// Output for a generic simulation.
+///
+/// Unknown keys are rejected, so removed options such as
+/// `topological_charge` must be moved to `quantities`.
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Output {
+    /// Per stage statistics, written to stdout if missing
+    pub stats: Option<StatOutput>,
+    /// Write the sample geometry to a parquet file
+    pub geometry: Option<PathBuf>,
     /// Write the observable data into the given file
     pub observables: Option<PathBuf>,
+    /// Names of the observables to record, all of the defaults if missing
+    pub quantities: Option<Vec<String>>,
     /// Write states to a parquet file
     pub state: Option<StateOutput>,
+    /// Write spin correlations to parquet files
+    pub correlation: Option<CorrelationOutput>,
+    /// Write spin snapshots for visualization tools
+    pub snapshot: Option<SnapshotOutput>,
+    /// Write spin states to an indexed binary trajectory
+    pub trajectory: Option<TrajectoryOutput>,
 }
 
 impl Default for Output {
     fn default() -> Self {
         Self {
+            stats: None,
+            geometry: None,
             observables: Some("./output.parquet".into()),
+            quantities: None,
             state: Some(StateOutput {
                 path: "./state.parquet".into(),
                 frequency: 1000,
             }),
+            correlation: None,
+            snapshot: None,
+            trajectory: None,
         }
     }
 }
@@ -144,6 +422,10 @@ pub enum Stage {
     CoolDown(CoolDown),
     /// Hysteresis loop
     Hysteresis(HysteresisLoop),
+    /// First-order reversal curves
+    Forc(Forc),
+    /// Arbitrary temperature and field schedule
+    Schedule(Schedule),
 }
 
 impl Default for Stage {
@@ -159,6 +441,17 @@ pub struct Input {
     model: Model,
     /// Algorithm to use
     algorithm: Algorithm,
+    /// Number of states of the Potts and clock models
+    states: Option<usize>,
+    /// Length of the Ising and Heisenberg spins, unit spins if missing
+    ///
+    /// Ising spins take multiples of 1/2 up to 7/2. Heisenberg spins take any
+    /// positive length and are simulated as unit spins with the exchange and
+    /// anisotropy scaled by `S²` and the field by `S`, so their
+    /// magnetization is given in units of `S`.
+    spin: Option<f64>,
+    /// Single ion anisotropy of the Ising and Heisenberg spins
+    anisotropy: Option<f64>,
     /// Exchange interaction
     exchange: Option<f64>,
     /// Sample to simulate
@@ -167,12 +460,27 @@ pub struct Input {
     stages: Vec<Stage>,
     /// Output for the simulation
     output: Option<Output>,
+    /// Verify the running energy and magnetization every given steps
+    verify_every: Option<usize>,
+    /// Seed of the random number generator, recorded in the outputs
+    seed: Option<u64>,
 }
 
 impl Input {
     pub fn builder() -> InputBuilder {
         InputBuilder::new()
     }
+
+    /// Seed of the random number generator, if any.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Set the seed recorded in the outputs.
+    pub fn set_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
 }
 
 impl Default for Input {
@@ -180,6 +488,9 @@ impl Default for Input {
         Input {
             model: Default::default(),
             algorithm: Default::default(),
+            states: None,
+            spin: None,
+            anisotropy: None,
             exchange: Default::default(),
             sample: Default::default(),
             stages: vec![
@@ -187,6 +498,8 @@ impl Default for Input {
                 Stage::CoolDown(CoolDown::default()),
             ],
             output: Some(Output::default()),
+            verify_every: None,
+            seed: None,
         }
     }
 }
@@ -194,10 +507,15 @@ impl Default for Input {
 pub struct InputBuilder {
     model: Option<Model>,
     algorithm: Option<Algorithm>,
+    states: Option<usize>,
+    spin: Option<f64>,
+    anisotropy: Option<f64>,
     exchange: Option<f64>,
     sample: Option<Sample>,
     steps: Option<Vec<Stage>>,
     output: Option<Output>,
+    verify_every: Option<usize>,
+    seed: Option<u64>,
 }
 
 impl InputBuilder {
@@ -205,10 +523,15 @@ impl InputBuilder {
         InputBuilder {
             model: None,
             algorithm: None,
+            states: None,
+            spin: None,
+            anisotropy: None,
             exchange: None,
             sample: None,
             steps: None,
             output: None,
+            verify_every: None,
+            seed: None,
         }
     }
 
@@ -222,6 +545,21 @@ impl InputBuilder {
         self
     }
 
+    pub fn states(mut self, states: usize) -> Self {
+        self.states = Some(states);
+        self
+    }
+
+    pub fn spin(mut self, spin: f64) -> Self {
+        self.spin = Some(spin);
+        self
+    }
+
+    pub fn anisotropy(mut self, anisotropy: f64) -> Self {
+        self.anisotropy = Some(anisotropy);
+        self
+    }
+
     pub fn exchange(mut self, exchange: f64) -> Self {
         self.exchange = Some(exchange);
         self
@@ -242,14 +580,29 @@ impl InputBuilder {
         self
     }
 
+    pub fn verify_every(mut self, steps: usize) -> Self {
+        self.verify_every = Some(steps);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
     pub fn build(self) -> Input {
         Input {
             model: self.model.unwrap_or_default(),
             algorithm: self.algorithm.unwrap_or_default(),
+            states: self.states,
+            spin: self.spin,
+            anisotropy: self.anisotropy,
             exchange: self.exchange,
             sample: self.sample.unwrap_or_default(),
             stages: self.steps.unwrap_or_default(),
             output: self.output,
+            verify_every: self.verify_every,
+            seed: self.seed,
         }
     }
 }
@@ -261,22 +614,28 @@ impl Default for InputBuilder {
 }
 
 impl Input {
-    fn run_with_spin<S: Spin + 'static, R: Rng, I: Integrator<S>>(
+    fn run_with_spin<S, R, I, H>(
         &self,
         rng: &mut R,
         integrator: I,
-        exchange: f64,
-    ) -> VegasResult<()> {
+        hamiltonian: H,
+    ) -> VegasResult<()>
+    where
+        S: Spin + 'static,
+        R: Rng,
+        I: Integrator<S>,
+        H: Hamiltonian<S> + 'static,
+    {
         let lattice = self.lattice();
-        let hamiltonian = hamiltonian!(Exchange::from_lattice(exchange, &lattice), Zeeman::new());
-        let instruments = self.instruments::<_, S>()?;
+        let instruments = self.instruments::<_, S>(&hamiltonian, &lattice)?;
         let mut machine = Machine::new(
             Thermostat::new(2.8, Field::zero()),
             hamiltonian,
             integrator,
             instruments,
-            State::<S>::rand_with_size(rng, lattice.sites().len()),
-        );
+            self.sample.initial.build(rng, &lattice)?,
+        )
+        .set_verify_every(self.verify_every);
         for program in self.stages.iter() {
             match program {
                 Stage::Relax(relax) => {
@@ -288,6 +647,12 @@ impl Input {
                 Stage::Hysteresis(hysteresis) => {
                     hysteresis.run(rng, &mut machine)?;
                 }
+                Stage::Forc(forc) => {
+                    forc.run(rng, &mut machine)?;
+                }
+                Stage::Schedule(schedule) => {
+                    schedule.run(rng, &mut machine)?;
+                }
             }
         }
         Ok(())
@@ -323,46 +688,496 @@ impl Input {
 
     fn instruments<H: Hamiltonian<S> + 'static, S: Spin + 'static>(
         &self,
+        hamiltonian: &H,
+        lattice: &Lattice,
     ) -> VegasResult<Vec<Box<dyn Instrument<H, S>>>> {
-        let mut instruments: Vec<Box<dyn Instrument<_, _>>> =
-            vec![Box::new(StatSensor::<_, S>::new(Box::new(stdout())))];
+        let metadata = self.metadata(lattice)?;
+        if let Some(path) = self
+            .output
+            .as_ref()
+            .and_then(|output| output.geometry.as_ref())
+        {
+            let mut geometry = GeometryParquetOutput::try_new(path)?;
+            geometry.set_metadata(&metadata);
+            geometry.write(lattice)?;
+        }
+        let mut instruments: Vec<Box<dyn Instrument<_, _>>> = Vec::new();
+        let stats = self
+            .output
+            .as_ref()
+            .and_then(|output| output.stats.as_ref());
+        match stats {
+            Some(stats) if stats.quiet => {}
+            Some(stats) => {
+                let writer: Box<dyn Write> = match &stats.path {
+                    Some(path) => {
+                        Box::new(BufWriter::new(File::create(path).map_err(IoError::from)?))
+                    }
+                    None => Box::new(stdout()),
+                };
+                instruments.push(Box::new(
+                    StatSensor::<_, S>::new(writer).set_format(stats.format),
+                ));
+            }
+            None => instruments.push(Box::new(StatSensor::<_, S>::new(Box::new(stdout())))),
+        }
         if let Some(output) = &self.output
             && let Some(observable_filename) = &output.observables
         {
-            instruments.push(Box::new(ObservableSensor::<_, S>::try_new(
-                observable_filename,
-            )?));
+            let observables = match &output.quantities {
+                Some(names) => names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        if names[..i].contains(name) {
+                            return Err(VegasError::DuplicateObservable(name.clone()));
+                        }
+                        observable::by_name(name, hamiltonian, lattice).ok_or_else(|| {
+                            VegasError::UnknownObservable(format!(
+                                "{}, expected one of {}",
+                                name,
+                                NAMES.join(", ")
+                            ))
+                        })
+                    })
+                    .collect::<VegasResult<Vec<_>>>()?,
+                None => observable::defaults(),
+            };
+            instruments.push(Box::new(
+                ObservableSensor::<_, S>::try_with_observables(observable_filename, observables)?
+                    .set_metadata(&metadata),
+            ));
         }
         if let Some(output) = &self.output
             && let Some(state_output) = &output.state
         {
-            instruments.push(Box::new(StateSensor::<_, S>::try_new(
-                &state_output.path,
-                state_output.frequency,
+            instruments.push(Box::new(
+                StateSensor::<_, S>::try_new(&state_output.path, state_output.frequency)?
+                    .set_metadata(&metadata),
+            ));
+        }
+        if let Some(output) = &self.output
+            && let Some(correlation) = &output.correlation
+        {
+            let mut sensor = CorrelationSensor::<_, S>::new(
+                Geometry::from_lattice(lattice),
+                correlation.frequency,
+                correlation.wave_vectors(),
+            );
+            if let Some(path) = &correlation.structure_factor {
+                sensor = sensor.set_structure_factor(path)?;
+            }
+            if let Some(path) = &correlation.pair_correlation {
+                sensor = sensor.set_pair_correlation(path)?;
+            }
+            instruments.push(Box::new(sensor.set_metadata(&metadata)));
+        }
+        if let Some(output) = &self.output
+            && let Some(snapshot) = &output.snapshot
+        {
+            instruments.push(Box::new(SnapshotSensor::<_, S>::try_new(
+                &snapshot.path,
+                snapshot.format,
+                lattice,
+                snapshot.frequency,
             )?));
         }
+        if let Some(output) = &self.output
+            && let Some(trajectory) = &output.trajectory
+        {
+            let encoding = match (&self.model, self.twice_spin()?) {
+                (Model::Ising, None | Some(1)) => TrajectoryEncoding::Bits,
+                _ => TrajectoryEncoding::quantized(trajectory.precision.unwrap_or(16))?,
+            };
+            instruments.push(Box::new(
+                TrajectorySensor::<_, S>::try_new(
+                    &trajectory.path,
+                    lattice.sites().len(),
+                    encoding,
+                    trajectory.frequency,
+                )?
+                .set_metadata(&metadata),
+            ));
+        }
         Ok(instruments)
     }
 
-    pub fn run<R: Rng>(&self, rng: &mut R) -> VegasResult<()> {
-        match (&self.model, &self.algorithm) {
-            (Model::Ising, Algorithm::Metropolis) => self.run_with_spin::<IsingSpin, _, _>(
+    /// Metadata describing the run, embedded in every parquet output.
+    ///
+    /// Holds the version of vegas, the full input as TOML, the seed, the
+    /// model, the algorithm and the dimensions of the sample.
+    pub fn metadata(&self, lattice: &Lattice) -> VegasResult<Metadata> {
+        let (x, y, z) = lattice.size();
+        let size = &self.sample.size;
+        let pbc = &self.sample.pbc;
+        let mut metadata = Metadata::new()
+            .set("vegas.version", env!("CARGO_PKG_VERSION"))
+            .set("vegas.input", toml::to_string(self)?)
+            .set("vegas.model", format!("{:?}", self.model))
+            .set("vegas.algorithm", format!("{:?}", self.algorithm))
+            .set("vegas.sites", lattice.sites().len())
+            .set("vegas.size", format!("{} {} {}", size.x, size.y, size.z))
+            .set("vegas.box", format!("{} {} {}", x, y, z))
+            .set("vegas.pbc", format!("{} {} {}", pbc.x, pbc.y, pbc.z));
+        if let Some(seed) = self.seed {
+            metadata = metadata.set("vegas.seed", seed);
+        }
+        Ok(metadata)
+    }
+
+    /// Exchange and Zeeman energy, scaled for unit spins standing in for
+    /// spins of the given length.
+    fn exchange_hamiltonian<S: Spin>(&self, length: f64) -> Compound<S, Exchange, Zeeman<S>> {
+        let exchange = self.exchange.unwrap_or(1.0) * length * length;
+        hamiltonian!(
+            Exchange::from_lattice(exchange, &self.lattice()),
+            Zeeman::with_moment(length)
+        )
+    }
+
+    /// Exchange, Zeeman and single ion energy, scaled like
+    /// `exchange_hamiltonian`.
+    fn anisotropic_hamiltonian<S: Spin>(
+        &self,
+        length: f64,
+    ) -> Compound<S, Compound<S, Exchange, Zeeman<S>>, SingleIonAnisotropy> {
+        let anisotropy = self.anisotropy.unwrap_or(0.0) * length * length;
+        hamiltonian!(
+            self.exchange_hamiltonian(length),
+            SingleIonAnisotropy::new(anisotropy)
+        )
+    }
+
+    fn run_potts<R: Rng>(&self, rng: &mut R, states: usize) -> VegasResult<()> {
+        match_states!(
+            states,
+            PottsSpin as T => self.run_with_spin::<T, _, _, _>(
                 rng,
-                MetropolisFlipIntegrator::new(),
-                self.exchange.unwrap_or(1.0),
+                MetropolisIntegrator::new(),
+                self.exchange_hamiltonian(1.0),
+            ),
+            _ => Err(VegasError::UnsupportedStates(states))
+        )
+    }
+
+    fn run_clock<R: Rng>(&self, rng: &mut R, states: usize) -> VegasResult<()> {
+        match_states!(
+            states,
+            ClockSpin as T => self.run_with_spin::<T, _, _, _>(
+                rng,
+                MetropolisIntegrator::new(),
+                self.exchange_hamiltonian(1.0),
             ),
-            (Model::Ising, Algorithm::Wolff) => self.run_with_spin::<IsingSpin, _, _>(
+            _ => Err(VegasError::UnsupportedStates(states))
+        )
+    }
+
+    fn run_ising_s<R: Rng>(&self, rng: &mut R, twice_spin: usize) -> VegasResult<()> {
+        match_spin!(
+            twice_spin,
+            IsingSpinS<1, 2, 3, 4, 5, 6, 7> as T => self.run_with_spin::<T, _, _, _>(
                 rng,
-                WolffIntegrator::from_lattice(self.exchange.unwrap_or(1.0), &self.lattice()),
-                self.exchange.unwrap_or(1.0),
+                MetropolisIntegrator::new(),
+                self.anisotropic_hamiltonian(1.0),
             ),
-            (Model::Heisenberg, Algorithm::Metropolis) => self
-                .run_with_spin::<HeisenbergSpin, _, _>(
+            _ => Err(VegasError::UnsupportedSpin(twice_spin as f64 / 2.0))
+        )
+    }
+
+    /// Twice the length of the Ising spins, when one is given.
+    ///
+    /// Rejects the parameters the model does not take first. Without a length Ising and Heisenberg models use unit spins, with
+    /// projections `±1` for Ising, whether there is an anisotropy or not.
+    /// Heisenberg spins of any length are unit spins with scaled couplings,
+    /// so they never need a length here.
+    fn twice_spin(&self) -> VegasResult<Option<usize>> {
+        if matches!(self.model, Model::Xy | Model::Potts | Model::Clock) {
+            let model = format!("{:?}", self.model);
+            if self.spin.is_some() {
+                return Err(VegasError::UnsupportedParameter("spin", model));
+            }
+            if self.anisotropy.is_some() {
+                return Err(VegasError::UnsupportedParameter("anisotropy", model));
+            }
+        }
+        if self.states.is_some() && !matches!(self.model, Model::Potts | Model::Clock) {
+            let model = format!("{:?}", self.model);
+            return Err(VegasError::UnsupportedParameter("states", model));
+        }
+        let Some(spin) = self.spin else {
+            return Ok(None);
+        };
+        if !spin.is_finite() || spin <= 0.0 {
+            return Err(VegasError::UnsupportedSpin(spin));
+        }
+        if matches!(self.model, Model::Heisenberg) {
+            return Ok(None);
+        }
+        let twice = 2.0 * spin;
+        if twice < 0.5 || (twice - twice.round()).abs() > 1e-9 {
+            return Err(VegasError::UnsupportedSpin(spin));
+        }
+        Ok(Some(twice.round() as usize))
+    }
+
+    pub fn run<R: Rng>(&self, rng: &mut R) -> VegasResult<()> {
+        let states = self.states.unwrap_or_else(|| self.model.default_states());
+        match (&self.model, &self.algorithm, self.twice_spin()?) {
+            (Model::Ising, Algorithm::Metropolis, Some(twice_spin)) => {
+                self.run_ising_s(rng, twice_spin)
+            }
+            (Model::BlumeCapel, Algorithm::Metropolis, twice_spin) => {
+                self.run_ising_s(rng, twice_spin.unwrap_or(2))
+            }
+            (Model::Ising, Algorithm::Metropolis, None) if self.anisotropy.is_some() => self
+                .run_with_spin::<IsingSpin, _, _, _>(
+                rng,
+                MetropolisFlipIntegrator::new(),
+                self.anisotropic_hamiltonian(1.0),
+            ),
+            (Model::Ising, Algorithm::Metropolis, None) => self
+                .run_with_spin::<IsingSpin, _, _, _>(
+                    rng,
+                    MetropolisFlipIntegrator::new(),
+                    self.exchange_hamiltonian(1.0),
+                ),
+            (Model::Ising, Algorithm::Wolff, None) if self.anisotropy.is_none() => self
+                .run_with_spin::<IsingSpin, _, _, _>(
+                    rng,
+                    WolffIntegrator::from_lattice(self.exchange.unwrap_or(1.0), &self.lattice()),
+                    self.exchange_hamiltonian(1.0),
+                ),
+            (Model::Heisenberg, Algorithm::Metropolis, _) if self.anisotropy.is_some() => {
+                self.run_with_spin::<HeisenbergSpin, _, _, _>(
                     rng,
                     MetropolisIntegrator::new(),
-                    self.exchange.unwrap_or(1.0),
+                    self.anisotropic_hamiltonian(self.spin.unwrap_or(1.0)),
+                )
+            }
+            (Model::Heisenberg, Algorithm::Metropolis, _) => self
+                .run_with_spin::<HeisenbergSpin, _, _, _>(
+                    rng,
+                    MetropolisIntegrator::new(),
+                    self.exchange_hamiltonian(self.spin.unwrap_or(1.0)),
                 ),
-            (Model::Heisenberg, Algorithm::Wolff) => Err(VegasError::NotImplementedError),
+            (Model::Xy, Algorithm::Metropolis, _) => self.run_with_spin::<XYSpin, _, _, _>(
+                rng,
+                MetropolisIntegrator::new(),
+                self.exchange_hamiltonian(1.0),
+            ),
+            (Model::Potts, Algorithm::Metropolis, _) => self.run_potts(rng, states),
+            (Model::Clock, Algorithm::Metropolis, _) => self.run_clock(rng, states),
+            (_, Algorithm::Wolff, _) => Err(VegasError::NotImplementedError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        output::StateParquetOutput,
+        state::{BlumeCapelSpin, XYSpin},
+    };
+    use rand::SeedableRng;
+    use rand_pcg::Pcg64;
+
+    #[test]
+    fn neel_state_is_antiparallel_along_every_bond() {
+        let lattice = Lattice::bcc(1.0).expand(2, 2, 2);
+        let state: State<IsingSpin> = InitialState::Neel
+            .build(&mut Pcg64::seed_from_u64(1), &lattice)
+            .unwrap();
+        assert!(
+            lattice
+                .edges()
+                .iter()
+                .all(|edge| state.at(edge.source()).dot(state.at(edge.target())) < 0.0)
+        );
+        assert!(state.magnetization().magnitude() < 1e-12);
+    }
+
+    #[test]
+    fn xy_spiral_rotates_in_the_plane() {
+        let lattice = Lattice::sc(1.0).expand(4, 1, 1);
+        let state: State<XYSpin> = InitialState::Spiral {
+            q: [0.25, 0.0, 0.0],
+        }
+        .build(&mut Pcg64::seed_from_u64(1), &lattice)
+        .unwrap();
+        let mut angles: Vec<f64> = state
+            .spins()
+            .iter()
+            .map(|spin| spin.sy().atan2(spin.sx()))
+            .collect();
+        angles.sort_by(f64::total_cmp);
+        assert!(angles.windows(2).all(|pair| pair[1] - pair[0] > 1.0));
+    }
+
+    #[test]
+    fn initial_state_loads_the_chosen_snapshot() {
+        let mut rng = Pcg64::seed_from_u64(2);
+        let lattice = Lattice::sc(1.0).expand(3, 3, 1);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.parquet");
+        let first = State::<BlumeCapelSpin>::rand_with_size(&mut rng, 9);
+        let second = State::<BlumeCapelSpin>::rand_with_size(&mut rng, 9);
+        {
+            let mut output = StateParquetOutput::try_new(&path).unwrap();
+            let thermostat = Thermostat::near_zero();
+            output.write(true, 0, 0, &thermostat, &first).unwrap();
+            output.write(true, 0, 1, &thermostat, &second).unwrap();
+        }
+        let initial = InitialState::File {
+            path: path.clone(),
+            stage: Some(0),
+            step: Some(0),
+        };
+        let loaded: State<BlumeCapelSpin> = initial.build(&mut rng, &lattice).unwrap();
+        assert_eq!(loaded.spins(), first.spins());
+        let initial = InitialState::File {
+            path: path.clone(),
+            stage: None,
+            step: None,
+        };
+        let loaded: State<BlumeCapelSpin> = initial.build(&mut rng, &lattice).unwrap();
+        assert_eq!(loaded.spins(), second.spins());
+    }
+
+    #[test]
+    fn outputs_record_the_run_in_their_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("observables.parquet");
+        let input = Input::builder()
+            .model(Model::Heisenberg)
+            .steps(vec![Stage::Relax(Relax::new(10, 1.0))])
+            .output(Output {
+                stats: Some(StatOutput {
+                    quiet: true,
+                    ..Default::default()
+                }),
+                geometry: None,
+                observables: Some(path.clone()),
+                quantities: None,
+                state: None,
+                correlation: None,
+                snapshot: None,
+                trajectory: None,
+            })
+            .seed(42)
+            .build();
+        input.run(&mut Pcg64::seed_from_u64(42)).unwrap();
+        let metadata = Metadata::read(&path).unwrap();
+        assert_eq!(
+            metadata.get("vegas.version"),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(metadata.get("vegas.seed"), Some("42"));
+        assert_eq!(metadata.get("vegas.model"), Some("Heisenberg"));
+        assert_eq!(metadata.get("vegas.sites"), Some("1"));
+        let recorded: Input = toml::from_str(metadata.get("vegas.input").unwrap()).unwrap();
+        assert_eq!(recorded.seed(), Some(42));
+    }
+
+    #[test]
+    fn output_rejects_removed_and_duplicate_observables() {
+        let removed = toml::from_str::<Output>(
+            "observables = \"output.parquet\"\ntopological_charge = true\n",
+        );
+        assert!(
+            removed
+                .unwrap_err()
+                .to_string()
+                .contains("topological_charge")
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let input = Input::builder()
+            .model(Model::Heisenberg)
+            .steps(vec![Stage::Relax(Relax::new(10, 1.0))])
+            .output(Output {
+                stats: Some(StatOutput {
+                    quiet: true,
+                    ..Default::default()
+                }),
+                geometry: None,
+                observables: Some(dir.path().join("observables.parquet")),
+                quantities: Some(vec!["energy".into(), "moves".into(), "energy".into()]),
+                state: None,
+                correlation: None,
+                snapshot: None,
+                trajectory: None,
+            })
+            .build();
+        assert!(matches!(
+            input.run(&mut Pcg64::seed_from_u64(1)),
+            Err(VegasError::DuplicateObservable(name)) if name == "energy"
+        ));
+    }
+
+    #[test]
+    fn spin_length_is_only_set_explicitly() {
+        let input = Input::builder().model(Model::Ising).anisotropy(0.5).build();
+        assert_eq!(input.twice_spin().unwrap(), None);
+        let input = Input::builder().model(Model::Ising).spin(1.5).build();
+        assert_eq!(input.twice_spin().unwrap(), Some(3));
+        let input = Input::builder().model(Model::Ising).spin(1.3).build();
+        assert!(matches!(
+            input.twice_spin(),
+            Err(VegasError::UnsupportedSpin(_))
+        ));
+        let input = Input::builder()
+            .model(Model::Heisenberg)
+            .spin(1.3)
+            .steps(vec![Stage::Relax(Relax::new(10, 1.0))])
+            .output(Output {
+                stats: Some(StatOutput {
+                    quiet: true,
+                    ..Default::default()
+                }),
+                geometry: None,
+                observables: None,
+                quantities: None,
+                state: None,
+                correlation: None,
+                snapshot: None,
+                trajectory: None,
+            })
+            .build();
+        assert_eq!(input.twice_spin().unwrap(), None);
+        input.run(&mut Pcg64::seed_from_u64(1)).unwrap();
+        let input = Input::builder().model(Model::Heisenberg).spin(-1.0).build();
+        assert!(matches!(
+            input.twice_spin(),
+            Err(VegasError::UnsupportedSpin(_))
+        ));
+        for model in [Model::Xy, Model::Potts, Model::Clock] {
+            let input = Input::builder().model(model.clone()).spin(1.0).build();
+            assert!(matches!(
+                input.twice_spin(),
+                Err(VegasError::UnsupportedParameter("spin", _))
+            ));
+            let input = Input::builder().model(model).anisotropy(0.5).build();
+            assert!(matches!(
+                input.run(&mut Pcg64::seed_from_u64(1)),
+                Err(VegasError::UnsupportedParameter("anisotropy", _))
+            ));
+        }
+        for model in [
+            Model::Ising,
+            Model::Heisenberg,
+            Model::Xy,
+            Model::BlumeCapel,
+        ] {
+            let input = Input::builder().model(model).states(4).build();
+            assert!(matches!(
+                input.twice_spin(),
+                Err(VegasError::UnsupportedParameter("states", _))
+            ));
         }
+        let input = Input::builder().model(Model::Potts).states(9).build();
+        assert!(matches!(
+            input.run(&mut Pcg64::seed_from_u64(1)),
+            Err(VegasError::UnsupportedStates(9))
+        ));
     }
 }
@@ -2,18 +2,29 @@
 //!
 //! This module defines instruments that can hook into the simulation process
 //! to monitor and record various statistics and states during the simulation.
-//! It includes instruments for recording statistical data and saving spin states
-//! to Parquet files.
+//! It includes instruments for recording statistical data, saving spin states
+//! to Parquet or indexed trajectory files and writing snapshots for
+//! visualization tools.
 
 use crate::{
     accumulator::Accumulator,
     energy::Hamiltonian,
     error::{InstrumentResult, IoResult},
-    output::{ObservableParquetOutput, StateParquetOutput},
+    geometry::Geometry,
+    machine::Totals,
+    observable::{self, Observable},
+    output::{
+        Metadata, ObservableParquetOutput, PairCorrelationParquetOutput, StateParquetOutput,
+        StructureFactorParquetOutput, WithMetadata,
+    },
+    snapshot::{Frame, SnapshotFormat, SnapshotWriter},
     state::{Spin, State},
     thermostat::Thermostat,
+    trajectory::{TrajectoryEncoding, TrajectoryWriter},
 };
-use std::{io::Write, marker::PhantomData, path::Path};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, f64::consts::PI, io::Write, marker::PhantomData, path::Path};
+use vegas_lattice::Lattice;
 
 /// An instrument allows to hook into the simulation at various points.
 pub trait Instrument<H, S>
@@ -31,6 +42,11 @@ where
         Ok(())
     }
 
+    /// Hook called when a relaxation reaches equilibrium after `steps` steps.
+    fn on_equilibrated(&mut self, _steps: usize) -> InstrumentResult<()> {
+        Ok(())
+    }
+
     /// Hook called when a relaxation ends.
     fn on_relax_end(&mut self) -> InstrumentResult<()> {
         Ok(())
@@ -51,25 +67,111 @@ where
         Ok(())
     }
 
-    /// Hook called after each integration step.
-    fn after_step(&mut self, _state: &State<S>) -> InstrumentResult<()> {
+    /// Hook called after each integration step with the running totals of
+    /// the state.
+    fn after_step(&mut self, _state: &State<S>, _totals: &Totals) -> InstrumentResult<()> {
         Ok(())
     }
 }
 
+/// Format of the lines written by the `StatSensor`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatFormat {
+    /// Space separated values with a header commented out with `#`
+    #[default]
+    Text,
+    /// Comma separated values with a header
+    Csv,
+    /// Tab separated values with a header
+    Tsv,
+    /// One JSON object per line, non finite values are written as null
+    Json,
+}
+
+impl StatFormat {
+    fn header(&self) -> Option<String> {
+        match self {
+            StatFormat::Text => Some(format!("# {}", STAT_COLUMNS.join(" "))),
+            StatFormat::Csv => Some(STAT_COLUMNS.join(",")),
+            StatFormat::Tsv => Some(STAT_COLUMNS.join("\t")),
+            StatFormat::Json => None,
+        }
+    }
+
+    fn line(&self, values: &[f64]) -> String {
+        let join = |separator: &str| {
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(separator)
+        };
+        match self {
+            StatFormat::Text => values
+                .iter()
+                .map(|v| format!("{:.16}", v))
+                .collect::<Vec<_>>()
+                .join(" "),
+            StatFormat::Csv => join(","),
+            StatFormat::Tsv => join("\t"),
+            StatFormat::Json => {
+                let fields: Vec<String> = STAT_COLUMNS
+                    .iter()
+                    .zip(values)
+                    .map(|(name, v)| {
+                        if v.is_finite() {
+                            format!("\"{}\":{}", name, v)
+                        } else {
+                            format!("\"{}\":null", name)
+                        }
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(","))
+            }
+        }
+    }
+}
+
+/// Columns written by the `StatSensor`.
+pub const STAT_COLUMNS: [&str; 14] = [
+    "temperature",
+    "field",
+    "energy",
+    "energy_error",
+    "specific_heat",
+    "specific_heat_error",
+    "magnetization",
+    "magnetization_error",
+    "susceptibility",
+    "susceptibility_error",
+    "binder_cumulant",
+    "binder_cumulant_error",
+    "m_parallel",
+    "m_parallel_error",
+];
+
 /// An instrument that writes "statistics" to a given file.
+///
+/// Every measurement writes one line with the columns in `STAT_COLUMNS`: the
+/// temperature and field followed by the total energy, specific heat per
+/// spin, total magnetization, susceptibility per spin, Binder cumulant and
+/// magnetization along the field, each one next to its error. A header is
+/// written before the first line unless the format is JSON lines.
 pub struct StatSensor<H, S>
 where
     H: Hamiltonian<S>,
     S: Spin,
 {
     output: Box<dyn Write>,
+    format: StatFormat,
+    header: bool,
     energy_acc: Accumulator,
     magnetization_acc: Accumulator,
+    parallel_acc: Accumulator,
     thermostat: Option<Thermostat<S>>,
-    hamiltonian: Option<H>,
     n: Option<usize>,
-    phantom: PhantomData<S>,
+    phantom: PhantomData<H>,
 }
 
 impl<H, S> StatSensor<H, S>
@@ -80,14 +182,22 @@ where
     pub fn new(output: Box<dyn Write>) -> Self {
         Self {
             output,
+            format: StatFormat::default(),
+            header: false,
             energy_acc: Accumulator::new(),
             magnetization_acc: Accumulator::new(),
+            parallel_acc: Accumulator::new(),
             thermostat: None,
-            hamiltonian: None,
             n: None,
             phantom: PhantomData,
         }
     }
+
+    /// Set the format of the written lines.
+    pub fn set_format(mut self, format: StatFormat) -> Self {
+        self.format = format;
+        self
+    }
 }
 
 impl<H, S> Instrument<H, S> for StatSensor<H, S>
@@ -98,63 +208,80 @@ where
     fn on_measure_start(
         &mut self,
         thermostat: &Thermostat<S>,
-        hamiltonian: &H,
+        _hamiltonian: &H,
         state: &State<S>,
     ) -> InstrumentResult<()> {
         self.thermostat = Some(thermostat.clone());
-        self.hamiltonian = Some(hamiltonian.clone());
         self.n = Some(state.len());
         Ok(())
     }
 
     fn on_measure_end(&mut self) -> InstrumentResult<()> {
-        if let (Some(thermostat), Some(_), Some(n)) = (&self.thermostat, &self.hamiltonian, self.n)
-        {
-            writeln!(
-                self.output,
-                "{:.16} {:.16} {:.16} {:.16} {:.16} {:.16} {:.16}",
+        if let (Some(thermostat), Some(n)) = (&self.thermostat, self.n) {
+            let heat = n as f64 * thermostat.temperature().powi(2);
+            let chi = n as f64 * thermostat.temperature();
+            let values = [
                 thermostat.temperature(),
-                thermostat.field().magnitude(),
+                thermostat.field().value(),
                 self.energy_acc.mean(),
-                self.energy_acc.variance() / (n as f64 * thermostat.temperature().powi(2)),
+                self.energy_acc.mean_error(),
+                self.energy_acc.variance() / heat,
+                self.energy_acc.variance_error() / heat,
                 self.magnetization_acc.mean(),
-                self.magnetization_acc.variance() / (n as f64 * thermostat.temperature()),
+                self.magnetization_acc.mean_error(),
+                self.magnetization_acc.variance() / chi,
+                self.magnetization_acc.variance_error() / chi,
                 self.magnetization_acc.binder_cumulant(),
-            )?;
+                self.magnetization_acc.binder_cumulant_error(),
+                self.parallel_acc.mean(),
+                self.parallel_acc.mean_error(),
+            ];
+            if !self.header {
+                if let Some(header) = self.format.header() {
+                    writeln!(self.output, "{}", header)?;
+                }
+                self.header = true;
+            }
+            writeln!(self.output, "{}", self.format.line(&values))?;
         }
         self.thermostat = None;
-        self.hamiltonian = None;
         self.n = None;
         self.energy_acc = Accumulator::new();
         self.magnetization_acc = Accumulator::new();
+        self.parallel_acc = Accumulator::new();
         Ok(())
     }
 
-    fn after_step(&mut self, state: &State<S>) -> InstrumentResult<()> {
-        if let (Some(thermostat), Some(hamiltonian)) = (&self.thermostat, &self.hamiltonian) {
-            let energy = hamiltonian.total_energy(thermostat, state);
-            let magnetization = state.magnetization().magnitude();
-            self.energy_acc.collect(energy);
-            self.magnetization_acc.collect(magnetization);
+    fn after_step(&mut self, _state: &State<S>, totals: &Totals) -> InstrumentResult<()> {
+        if let Some(thermostat) = &self.thermostat {
+            let magnetization = totals.magnetization::<S>();
+            self.energy_acc.collect(totals.energy());
+            self.magnetization_acc.collect(magnetization.magnitude());
+            self.parallel_acc
+                .collect(thermostat.field().project(&magnetization));
         }
         Ok(())
     }
 }
 
 /// An instrument that stores observables in a parquet file.
+///
+/// The file has one column per value of the registered observables, which
+/// default to the energy and the magnetization.
 pub struct ObservableSensor<H, S>
 where
     H: Hamiltonian<S>,
     S: Spin,
 {
     io: ObservableParquetOutput,
+    observables: Vec<Box<dyn Observable<H, S>>>,
     stage: usize,
+    equilibrated: Option<usize>,
     thermostat: Option<Thermostat<S>>,
     hamiltonian: Option<H>,
     n: Option<usize>,
-    energy: Vec<f64>,
-    magnetization: Vec<f64>,
-    phantom: PhantomData<S>,
+    values: Vec<Vec<f64>>,
+    buffer: Vec<f64>,
 }
 
 impl<H, S> ObservableSensor<H, S>
@@ -163,116 +290,181 @@ where
     S: Spin,
 {
     pub fn try_new<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        Self::try_with_observables(path, observable::defaults())
+    }
+
+    /// Create a sensor that records the given observables.
+    pub fn try_with_observables<P: AsRef<Path>>(
+        path: P,
+        observables: Vec<Box<dyn Observable<H, S>>>,
+    ) -> IoResult<Self> {
+        let columns: Vec<String> = observables.iter().flat_map(|o| o.columns()).collect();
         Ok(Self {
-            io: ObservableParquetOutput::try_new(path)?,
+            io: ObservableParquetOutput::try_new(path, &columns)?,
+            observables,
             stage: 0,
+            equilibrated: None,
             thermostat: None,
             hamiltonian: None,
             n: None,
-            energy: Vec::new(),
-            magnetization: Vec::new(),
-            phantom: PhantomData,
+            values: vec![Vec::new(); columns.len()],
+            buffer: Vec::with_capacity(columns.len()),
         })
     }
-}
 
-impl<H, S> Instrument<H, S> for ObservableSensor<H, S>
-where
-    H: Hamiltonian<S>,
-    S: Spin,
-{
-    fn on_relax_start(
-        &mut self,
-        thermostat: &Thermostat<S>,
-        hamiltonian: &H,
-        state: &State<S>,
-    ) -> InstrumentResult<()> {
+    /// Embed the given metadata in the observables file.
+    pub fn set_metadata(mut self, metadata: &Metadata) -> Self {
+        self.io.set_metadata(metadata);
+        self
+    }
+
+    fn start(&mut self, thermostat: &Thermostat<S>, hamiltonian: &H, state: &State<S>) {
         self.thermostat = Some(thermostat.clone());
         self.hamiltonian = Some(hamiltonian.clone());
         self.n = Some(state.len());
-        self.energy.clear();
-        self.magnetization.clear();
-        Ok(())
+        self.values.iter_mut().for_each(Vec::clear);
     }
 
-    fn on_relax_end(&mut self) -> InstrumentResult<()> {
-        if let (Some(thermostat), Some(_), Some(n)) = (&self.thermostat, &self.hamiltonian, self.n)
-        {
+    fn end(&mut self, relax: bool) -> InstrumentResult<()> {
+        if let (Some(thermostat), Some(n)) = (&self.thermostat, self.n) {
             self.io.write(
-                true,
+                relax,
+                self.equilibrated.filter(|_| relax),
                 self.stage,
                 n,
                 thermostat,
-                &self.energy,
-                &self.magnetization,
+                &self.values,
             )?;
         }
         self.stage += 1;
+        self.equilibrated = None;
         self.hamiltonian = None;
         self.thermostat = None;
         self.n = None;
-        self.energy.clear();
-        self.magnetization.clear();
+        self.values.iter_mut().for_each(Vec::clear);
+        Ok(())
+    }
+}
+
+impl<H, S> Instrument<H, S> for ObservableSensor<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn on_relax_start(
+        &mut self,
+        thermostat: &Thermostat<S>,
+        hamiltonian: &H,
+        state: &State<S>,
+    ) -> InstrumentResult<()> {
+        self.start(thermostat, hamiltonian, state);
+        Ok(())
+    }
+
+    fn on_equilibrated(&mut self, steps: usize) -> InstrumentResult<()> {
+        self.equilibrated = Some(steps);
         Ok(())
     }
 
+    fn on_relax_end(&mut self) -> InstrumentResult<()> {
+        self.end(true)
+    }
+
     fn on_measure_start(
         &mut self,
         thermostat: &Thermostat<S>,
         hamiltonian: &H,
         state: &State<S>,
     ) -> InstrumentResult<()> {
-        self.thermostat = Some(thermostat.clone());
-        self.hamiltonian = Some(hamiltonian.clone());
-        self.n = Some(state.len());
-        self.energy.clear();
-        self.magnetization.clear();
+        self.start(thermostat, hamiltonian, state);
         Ok(())
     }
 
     fn on_measure_end(&mut self) -> InstrumentResult<()> {
-        if let (Some(thermostat), Some(_), Some(n)) = (&self.thermostat, &self.hamiltonian, self.n)
-        {
-            self.io.write(
-                false,
-                self.stage,
-                n,
-                thermostat,
-                &self.energy,
-                &self.magnetization,
-            )?;
-        }
-        self.stage += 1;
-        self.hamiltonian = None;
-        self.thermostat = None;
-        self.n = None;
-        self.energy.clear();
-        self.magnetization.clear();
-        Ok(())
+        self.end(false)
     }
 
-    fn after_step(&mut self, state: &State<S>) -> InstrumentResult<()> {
+    fn after_step(&mut self, state: &State<S>, totals: &Totals) -> InstrumentResult<()> {
         if let (Some(thermostat), Some(hamiltonian)) = (&self.thermostat, &self.hamiltonian) {
-            let energy = hamiltonian.total_energy(thermostat, state);
-            let magnetization = state.magnetization().magnitude();
-            self.energy.push(energy);
-            self.magnetization.push(magnetization);
+            self.buffer.clear();
+            for observable in &self.observables {
+                observable.measure(thermostat, hamiltonian, state, totals, &mut self.buffer);
+            }
+            debug_assert!(self.buffer.len() == self.values.len());
+            for (column, &value) in self.values.iter_mut().zip(&self.buffer) {
+                column.push(value);
+            }
         }
         Ok(())
     }
 }
 
+/// Stage bookkeeping of the sensors that record frames of the spins.
+///
+/// A frame is due every `frequency` steps of a stage, starting with its
+/// first step, so a frequency of zero records the first step only.
+struct FrameSchedule<S: Spin> {
+    frequency: usize,
+    relax: Option<bool>,
+    stage: usize,
+    step: usize,
+    thermostat: Option<Thermostat<S>>,
+}
+
+impl<S: Spin> FrameSchedule<S> {
+    fn new(frequency: usize) -> Self {
+        Self {
+            frequency,
+            relax: None,
+            stage: 0,
+            step: 0,
+            thermostat: None,
+        }
+    }
+
+    fn start(&mut self, relax: bool, thermostat: &Thermostat<S>) {
+        self.relax = Some(relax);
+        self.thermostat = Some(thermostat.clone());
+    }
+
+    fn end(&mut self) {
+        self.relax = None;
+        self.step = 0;
+        self.stage += 1;
+        self.thermostat = None;
+    }
+
+    /// The frame of the current step and its thermostat, if one is due.
+    fn due(&self) -> Option<(Frame, &Thermostat<S>)> {
+        let relax = self.relax?;
+        let thermostat = self.thermostat.as_ref()?;
+        self.step.is_multiple_of(self.frequency).then_some((
+            Frame {
+                relax,
+                stage: self.stage,
+                step: self.step,
+            },
+            thermostat,
+        ))
+    }
+
+    fn advance(&mut self) {
+        self.step += 1;
+    }
+}
+
+/// An instrument that writes the spin state to a parquet file.
+///
+/// Every `frequency` steps of every stage the spins are written along with
+/// the stage, step and thermostat, ready to be read back by a
+/// `StateParquetReader`.
 pub struct StateSensor<H, S>
 where
     H: Hamiltonian<S>,
     S: Spin,
 {
     io: StateParquetOutput,
-    frequency: usize,
-    relax: Option<bool>,
-    step: usize,
-    stage: usize,
-    thermostat: Option<Thermostat<S>>,
+    schedule: FrameSchedule<S>,
     phantom: PhantomData<H>,
 }
 
@@ -284,14 +476,16 @@ where
     pub fn try_new<P: AsRef<Path>>(path: P, frequency: usize) -> IoResult<Self> {
         Ok(Self {
             io: StateParquetOutput::try_new(path)?,
-            frequency,
-            relax: None,
-            stage: 0,
-            step: 0,
-            thermostat: None,
+            schedule: FrameSchedule::new(frequency),
             phantom: PhantomData,
         })
     }
+
+    /// Embed the given metadata in the state file.
+    pub fn set_metadata(mut self, metadata: &Metadata) -> Self {
+        self.io.set_metadata(metadata);
+        self
+    }
 }
 
 impl<H, S> Instrument<H, S> for StateSensor<H, S>
@@ -305,16 +499,12 @@ where
         _hamiltonian: &H,
         _state: &State<S>,
     ) -> InstrumentResult<()> {
-        self.relax = Some(true);
-        self.thermostat = Some(thermostat.clone());
+        self.schedule.start(true, thermostat);
         Ok(())
     }
 
     fn on_relax_end(&mut self) -> InstrumentResult<()> {
-        self.relax = None;
-        self.step = 0;
-        self.stage += 1;
-        self.thermostat = None;
+        self.schedule.end();
         Ok(())
     }
 
@@ -324,28 +514,548 @@ where
         _hamiltonian: &H,
         _state: &State<S>,
     ) -> InstrumentResult<()> {
-        self.relax = Some(false);
-        self.thermostat = Some(thermostat.clone());
+        self.schedule.start(false, thermostat);
         Ok(())
     }
 
     fn on_measure_end(&mut self) -> InstrumentResult<()> {
-        self.relax = None;
+        self.schedule.end();
+        Ok(())
+    }
+
+    fn after_step(&mut self, state: &State<S>, _totals: &Totals) -> InstrumentResult<()> {
+        if let Some((frame, thermostat)) = self.schedule.due() {
+            self.io
+                .write(frame.relax, frame.stage, frame.step, thermostat, state)?;
+        }
+        self.schedule.advance();
+        Ok(())
+    }
+}
+
+/// An instrument that writes the spin state to an indexed trajectory file.
+///
+/// Frames are compact, quantized to the precision of the encoding, and can
+/// be read back in any order with a `TrajectoryReader`.
+pub struct TrajectorySensor<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    io: TrajectoryWriter,
+    schedule: FrameSchedule<S>,
+    phantom: PhantomData<H>,
+}
+
+impl<H, S> TrajectorySensor<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    pub fn try_new<P: AsRef<Path>>(
+        path: P,
+        sites: usize,
+        encoding: TrajectoryEncoding,
+        frequency: usize,
+    ) -> IoResult<Self> {
+        Ok(Self {
+            io: TrajectoryWriter::try_new(path, sites, encoding)?,
+            schedule: FrameSchedule::new(frequency),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Embed the given metadata in the trajectory file.
+    pub fn set_metadata(mut self, metadata: &Metadata) -> Self {
+        self.io.set_metadata(metadata);
+        self
+    }
+}
+
+impl<H, S> Instrument<H, S> for TrajectorySensor<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn on_relax_start(
+        &mut self,
+        thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        _state: &State<S>,
+    ) -> InstrumentResult<()> {
+        self.schedule.start(true, thermostat);
+        Ok(())
+    }
+
+    fn on_relax_end(&mut self) -> InstrumentResult<()> {
+        self.schedule.end();
+        Ok(())
+    }
+
+    fn on_measure_start(
+        &mut self,
+        thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        _state: &State<S>,
+    ) -> InstrumentResult<()> {
+        self.schedule.start(false, thermostat);
+        Ok(())
+    }
+
+    fn on_measure_end(&mut self) -> InstrumentResult<()> {
+        self.schedule.end();
+        Ok(())
+    }
+
+    fn after_step(&mut self, state: &State<S>, _totals: &Totals) -> InstrumentResult<()> {
+        if let Some((frame, thermostat)) = self.schedule.due() {
+            self.io
+                .write(frame.relax, frame.stage, frame.step, thermostat, state)?;
+        }
+        self.schedule.advance();
+        Ok(())
+    }
+}
+
+/// An instrument that writes spin snapshots for visualization tools.
+///
+/// Snapshots hold the positions of the sites next to their spins, as
+/// extended XYZ or legacy VTK files that ParaView or OVITO can open.
+pub struct SnapshotSensor<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    io: SnapshotWriter,
+    schedule: FrameSchedule<S>,
+    phantom: PhantomData<H>,
+}
+
+impl<H, S> SnapshotSensor<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    pub fn try_new<P: AsRef<Path>>(
+        path: P,
+        format: SnapshotFormat,
+        lattice: &Lattice,
+        frequency: usize,
+    ) -> IoResult<Self> {
+        Ok(Self {
+            io: SnapshotWriter::try_new(path, format, lattice)?,
+            schedule: FrameSchedule::new(frequency),
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<H, S> Instrument<H, S> for SnapshotSensor<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn on_relax_start(
+        &mut self,
+        thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        _state: &State<S>,
+    ) -> InstrumentResult<()> {
+        self.schedule.start(true, thermostat);
+        Ok(())
+    }
+
+    fn on_relax_end(&mut self) -> InstrumentResult<()> {
+        self.schedule.end();
+        Ok(())
+    }
+
+    fn on_measure_start(
+        &mut self,
+        thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        _state: &State<S>,
+    ) -> InstrumentResult<()> {
+        self.schedule.start(false, thermostat);
+        Ok(())
+    }
+
+    fn on_measure_end(&mut self) -> InstrumentResult<()> {
+        self.schedule.end();
+        Ok(())
+    }
+
+    fn after_step(&mut self, state: &State<S>, _totals: &Totals) -> InstrumentResult<()> {
+        if let Some((frame, thermostat)) = self.schedule.due() {
+            self.io.write(frame, thermostat, state)?;
+        }
+        self.schedule.advance();
+        Ok(())
+    }
+}
+
+/// Resolution used to group pair distances into shells.
+const SHELL_RESOLUTION: f64 = 1e-6;
+
+fn shell_key(distance: f64) -> i64 {
+    (distance / SHELL_RESOLUTION).round() as i64
+}
+
+/// An instrument that measures spin-spin correlations during measurements.
+///
+/// Every `frequency` steps it samples the static structure factor
+/// `S(q) = ⟨|Σ_j S_j exp(i 2π q·r_j)|²⟩ / N` on the given wave vectors and
+/// the pair correlation `G(r) = ⟨S_i·S_j⟩` averaged over shells of equal
+/// minimum image distance. The connected correlation subtracts `⟨m⟩·⟨m⟩`,
+/// with `m` the magnetization per spin. Results are written once per
+/// measurement stage.
+///
+/// The shell of every pair of sites is computed once when the pair
+/// correlation output is set, which takes `N (N + 1) / 2` entries.
+pub struct CorrelationSensor<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    structure_factor_io: Option<StructureFactorParquetOutput>,
+    pair_correlation_io: Option<PairCorrelationParquetOutput>,
+    geometry: Geometry,
+    frequency: usize,
+    q: Vec<[f64; 3]>,
+    pair_shells: Vec<u32>,
+    distances: Vec<f64>,
+    pairs: Vec<usize>,
+    stage: usize,
+    step: usize,
+    samples: usize,
+    thermostat: Option<Thermostat<S>>,
+    structure_factor: Vec<f64>,
+    correlation: Vec<f64>,
+    magnetization: [f64; 3],
+    phantom: PhantomData<H>,
+}
+
+impl<H, S> CorrelationSensor<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    /// Create a sensor that samples every `frequency` steps of the
+    /// measurements, with wave vectors `q` in units of `2π / a`.
+    ///
+    /// Nothing is recorded until an output is set.
+    pub fn new(geometry: Geometry, frequency: usize, q: Vec<[f64; 3]>) -> Self {
+        Self {
+            structure_factor_io: None,
+            pair_correlation_io: None,
+            geometry,
+            frequency: frequency.max(1),
+            structure_factor: vec![0.0; q.len()],
+            q,
+            pair_shells: Vec::new(),
+            correlation: Vec::new(),
+            distances: Vec::new(),
+            pairs: Vec::new(),
+            stage: 0,
+            step: 0,
+            samples: 0,
+            thermostat: None,
+            magnetization: [0.0; 3],
+            phantom: PhantomData,
+        }
+    }
+
+    /// Write the structure factor to the given parquet file.
+    pub fn set_structure_factor<P: AsRef<Path>>(mut self, path: P) -> IoResult<Self> {
+        self.structure_factor_io = Some(StructureFactorParquetOutput::try_new(path)?);
+        Ok(self)
+    }
+
+    /// Write the pair correlation to the given parquet file.
+    pub fn set_pair_correlation<P: AsRef<Path>>(mut self, path: P) -> IoResult<Self> {
+        self.pair_correlation_io = Some(PairCorrelationParquetOutput::try_new(path)?);
+        self.find_shells();
+        Ok(self)
+    }
+
+    /// Group the pairs of sites into shells of equal distance.
+    fn find_shells(&mut self) {
+        let n = self.geometry.len();
+        let mut shells = BTreeMap::new();
+        for i in 0..n {
+            for j in i..n {
+                *shells
+                    .entry(shell_key(self.geometry.distance(i, j)))
+                    .or_insert(0usize) += 1;
+            }
+        }
+        let index: BTreeMap<i64, u32> = shells
+            .keys()
+            .enumerate()
+            .map(|(index, &key)| (key, index as u32))
+            .collect();
+        self.pair_shells = Vec::with_capacity(n * (n + 1) / 2);
+        for i in 0..n {
+            for j in i..n {
+                self.pair_shells
+                    .push(index[&shell_key(self.geometry.distance(i, j))]);
+            }
+        }
+        self.distances = shells
+            .keys()
+            .map(|&key| key as f64 * SHELL_RESOLUTION)
+            .collect();
+        self.pairs = shells.into_values().collect();
+        self.correlation = vec![0.0; self.pairs.len()];
+    }
+
+    /// Embed the given metadata in the files set so far.
+    pub fn set_metadata(mut self, metadata: &Metadata) -> Self {
+        if let Some(io) = &mut self.structure_factor_io {
+            io.set_metadata(metadata);
+        }
+        if let Some(io) = &mut self.pair_correlation_io {
+            io.set_metadata(metadata);
+        }
+        self
+    }
+
+    fn sample_structure_factor(&mut self, state: &State<S>) {
+        let positions = self.geometry.positions();
+        for (q, acc) in self.q.iter().zip(self.structure_factor.iter_mut()) {
+            let mut re = [0.0; 3];
+            let mut im = [0.0; 3];
+            for (spin, r) in state.spins().iter().zip(positions) {
+                let phase = 2.0 * PI * (q[0] * r[0] + q[1] * r[1] + q[2] * r[2]);
+                let (sin, cos) = phase.sin_cos();
+                for (c, s) in [spin.sx(), spin.sy(), spin.sz()].into_iter().enumerate() {
+                    re[c] += s * cos;
+                    im[c] += s * sin;
+                }
+            }
+            let intensity: f64 = (0..3).map(|c| re[c] * re[c] + im[c] * im[c]).sum();
+            *acc += intensity / state.len() as f64;
+        }
+    }
+
+    fn sample_pair_correlation(&mut self, state: &State<S>) {
+        let spins = state.spins();
+        let mut shells = self.pair_shells.iter();
+        for (i, a) in spins.iter().enumerate() {
+            for (b, &shell) in spins[i..].iter().zip(shells.by_ref()) {
+                self.correlation[shell as usize] += a.dot(b);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
         self.step = 0;
-        self.stage += 1;
+        self.samples = 0;
         self.thermostat = None;
+        self.structure_factor.iter_mut().for_each(|v| *v = 0.0);
+        self.correlation.iter_mut().for_each(|v| *v = 0.0);
+        self.magnetization = [0.0; 3];
+    }
+}
+
+impl<H, S> Instrument<H, S> for CorrelationSensor<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn on_relax_end(&mut self) -> InstrumentResult<()> {
+        self.stage += 1;
+        self.reset();
+        Ok(())
+    }
+
+    fn on_measure_start(
+        &mut self,
+        thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        state: &State<S>,
+    ) -> InstrumentResult<()> {
+        debug_assert!(state.len() == self.geometry.len());
+        self.reset();
+        self.thermostat = Some(thermostat.clone());
         Ok(())
     }
 
-    fn after_step(&mut self, state: &State<S>) -> InstrumentResult<()> {
-        if self.step.is_multiple_of(self.frequency)
-            && let Some(relax) = self.relax
-            && let Some(thermostat) = &self.thermostat
+    fn on_measure_end(&mut self) -> InstrumentResult<()> {
+        if let Some(thermostat) = &self.thermostat
+            && self.samples > 0
         {
-            self.io
-                .write(relax, self.stage, self.step, thermostat, state)?;
+            let samples = self.samples as f64;
+            let m = self.magnetization.map(|m| m / samples);
+            let m2 = m[0] * m[0] + m[1] * m[1] + m[2] * m[2];
+            if let Some(io) = &mut self.structure_factor_io {
+                let structure_factor: Vec<f64> =
+                    self.structure_factor.iter().map(|v| v / samples).collect();
+                io.write(self.stage, thermostat, &self.q, &structure_factor)?;
+            }
+            if let Some(io) = &mut self.pair_correlation_io {
+                let correlation: Vec<f64> = self
+                    .correlation
+                    .iter()
+                    .zip(&self.pairs)
+                    .map(|(v, &pairs)| v / (samples * pairs as f64))
+                    .collect();
+                let connected: Vec<f64> = correlation.iter().map(|g| g - m2).collect();
+                io.write(
+                    self.stage,
+                    thermostat,
+                    &self.distances,
+                    &self.pairs,
+                    &correlation,
+                    &connected,
+                )?;
+            }
+        }
+        self.stage += 1;
+        self.reset();
+        Ok(())
+    }
+
+    fn after_step(&mut self, state: &State<S>, _totals: &Totals) -> InstrumentResult<()> {
+        if self.thermostat.is_none() {
+            return Ok(());
+        }
+        if self.step.is_multiple_of(self.frequency) {
+            let n = state.len() as f64;
+            for spin in state.spins() {
+                self.magnetization[0] += spin.sx() / n;
+                self.magnetization[1] += spin.sy() / n;
+                self.magnetization[2] += spin.sz() / n;
+            }
+            if self.structure_factor_io.is_some() {
+                self.sample_structure_factor(state);
+            }
+            if self.pair_correlation_io.is_some() {
+                self.sample_pair_correlation(state);
+            }
+            self.samples += 1;
         }
         self.step += 1;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{energy::Exchange, state::HeisenbergSpin};
+    use arrow::{array::AsArray, datatypes::Float64Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::File;
+
+    fn read_column(path: &Path, name: &str) -> Vec<f64> {
+        ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .flat_map(|batch| {
+                let batch = batch.unwrap();
+                batch
+                    .column_by_name(name)
+                    .unwrap()
+                    .as_primitive::<Float64Type>()
+                    .values()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stat_formats_name_every_column() {
+        let values: Vec<f64> = (0..STAT_COLUMNS.len()).map(|i| i as f64 + 0.5).collect();
+        let csv = StatFormat::Csv.header().unwrap();
+        assert_eq!(csv.split(',').count(), STAT_COLUMNS.len());
+        assert_eq!(
+            StatFormat::Tsv.line(&values).split('\t').count(),
+            STAT_COLUMNS.len()
+        );
+        assert!(
+            StatFormat::Text
+                .header()
+                .unwrap()
+                .starts_with("# temperature field")
+        );
+        assert_eq!(StatFormat::Json.header(), None);
+        let mut values = values;
+        values[3] = f64::NAN;
+        let json = StatFormat::Json.line(&values);
+        assert!(json.starts_with("{\"temperature\":0.5,\"field\":1.5,"));
+        assert!(json.contains("\"energy_error\":null"));
+    }
+
+    #[test]
+    fn aligned_state_is_fully_correlated() {
+        let lattice = Lattice::sc(1.0).expand(3, 3, 2);
+        let hamiltonian = Exchange::from_lattice(1.0, &lattice);
+        let thermostat = Thermostat::new(1.0, Default::default());
+        let state = State::<HeisenbergSpin>::up_with_size(lattice.sites().len());
+        let totals = Totals::of(&thermostat, &hamiltonian, &state);
+        let dir = tempfile::tempdir().unwrap();
+        let structure_factor = dir.path().join("structure_factor.parquet");
+        let pair_correlation = dir.path().join("pair_correlation.parquet");
+        {
+            let mut sensor = CorrelationSensor::new(
+                Geometry::from_lattice(&lattice),
+                2,
+                vec![[0.0, 0.0, 0.0], [1.0 / 3.0, 0.0, 0.0]],
+            )
+            .set_structure_factor(&structure_factor)
+            .unwrap()
+            .set_pair_correlation(&pair_correlation)
+            .unwrap();
+            sensor
+                .on_measure_start(&thermostat, &hamiltonian, &state)
+                .unwrap();
+            for _ in 0..5 {
+                sensor.after_step(&state, &totals).unwrap();
+            }
+            sensor.on_measure_end().unwrap();
+        }
+        let s = read_column(&structure_factor, "structure_factor");
+        assert!((s[0] - state.len() as f64).abs() < 1e-9);
+        assert!(s[1].abs() < 1e-9);
+        let correlation = read_column(&pair_correlation, "correlation");
+        assert!(correlation.len() > 1);
+        assert!(correlation.iter().all(|g| (g - 1.0).abs() < 1e-12));
+        let connected = read_column(&pair_correlation, "connected");
+        assert!(connected.iter().all(|g| g.abs() < 1e-12));
+    }
+
+    #[test]
+    fn frames_are_due_on_the_same_steps_of_every_stage() {
+        let thermostat = Thermostat::<HeisenbergSpin>::near_zero();
+        let due_steps = |frequency: usize| {
+            let mut schedule = FrameSchedule::new(frequency);
+            let mut due = Vec::new();
+            for relax in [true, false] {
+                assert!(schedule.due().is_none());
+                schedule.start(relax, &thermostat);
+                for _ in 0..7 {
+                    if let Some((frame, _)) = schedule.due() {
+                        due.push((frame.relax, frame.stage, frame.step));
+                    }
+                    schedule.advance();
+                }
+                schedule.end();
+            }
+            due
+        };
+        assert_eq!(
+            due_steps(3),
+            [
+                (true, 0, 0),
+                (true, 0, 3),
+                (true, 0, 6),
+                (false, 1, 0),
+                (false, 1, 3),
+                (false, 1, 6)
+            ]
+        );
+        assert_eq!(due_steps(0), [(true, 0, 0), (false, 1, 0)]);
+    }
+}
@@ -18,10 +18,10 @@
 //! // Define a Hamiltonian (e.g., Gauge Hamiltonian).
 //! let hamiltonian = Gauge::new(1.0);
 //! let thermostat = Thermostat::new(2.5, Field::zero());
-//! let integrator = MetropolisFlipIntegrator::new();
+//! let mut integrator = MetropolisFlipIntegrator::new();
 //! let mut rng = thread_rng();
-//! let state: State<IsingSpin> = State::rand_with_size(&mut rng, 100);
-//! let new_state = integrator.step(&mut rng, &thermostat, &hamiltonian, state);
+//! let mut state: State<IsingSpin> = State::rand_with_size(&mut rng, 100);
+//! let report = integrator.step(&mut rng, &thermostat, &hamiltonian, &mut state);
 //! ```
 
 use std::collections::VecDeque;
@@ -35,17 +35,69 @@ use rand::Rng;
 use rand::distr::{Distribution, Uniform};
 use vegas_lattice::Lattice;
 
+/// Changes caused by a single step of an integrator and its move statistics.
+///
+/// Energy changes are computed from the site energies of the hamiltonian,
+/// which must include every bond of the site so that the change of a site
+/// energy is the change of the total energy.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StepReport {
+    /// Change of the total energy.
+    pub energy: f64,
+    /// Change of the magnetization vector.
+    pub magnetization: [f64; 3],
+    /// Number of proposed moves.
+    pub proposed: usize,
+    /// Number of accepted moves.
+    pub accepted: usize,
+    /// Number of spins changed by the accepted moves.
+    pub flipped: usize,
+}
+
+impl StepReport {
+    /// Record the replacement of spin `old` by spin `new`.
+    fn replace<S: Spin>(&mut self, delta: f64, old: &S, new: &S) {
+        self.energy += delta;
+        self.magnetization[0] += new.sx() - old.sx();
+        self.magnetization[1] += new.sy() - old.sy();
+        self.magnetization[2] += new.sz() - old.sz();
+        self.accepted += 1;
+        self.flipped += 1;
+    }
+
+    /// Fraction of the proposed moves that were accepted.
+    pub fn acceptance(&self) -> f64 {
+        if self.proposed == 0 {
+            0.0
+        } else {
+            self.accepted as f64 / self.proposed as f64
+        }
+    }
+
+    /// Mean number of spins changed by an accepted move, the cluster size
+    /// for cluster algorithms.
+    pub fn mean_cluster_size(&self) -> f64 {
+        if self.accepted == 0 {
+            0.0
+        } else {
+            self.flipped as f64 / self.accepted as f64
+        }
+    }
+}
+
 /// An integrator is a method that allows you to sample the phase space of a
 /// system.
 pub trait Integrator<S: Spin> {
-    /// Perform a single step of the integrator.
+    /// Perform a single step of the integrator, updating the state in place.
+    ///
+    /// Returns the changes of the energy and magnetization of the state.
     fn step<R: Rng, H: Hamiltonian<S>>(
-        &self,
+        &mut self,
         rng: &mut R,
         thermostat: &Thermostat<S>,
         hamiltonian: &H,
-        state: State<S>,
-    ) -> State<S>;
+        state: &mut State<S>,
+    ) -> StepReport;
 }
 
 /// The most common integrator is the Metropolis integrator.
@@ -65,29 +117,31 @@ impl MetropolisIntegrator {
 
 impl<S: Spin> Integrator<S> for MetropolisIntegrator {
     fn step<R: Rng, H: Hamiltonian<S>>(
-        &self,
+        &mut self,
         rng: &mut R,
         thermostat: &Thermostat<S>,
         hamiltonian: &H,
-        mut state: State<S>,
-    ) -> State<S> {
+        state: &mut State<S>,
+    ) -> StepReport {
+        let mut report = StepReport {
+            proposed: state.len(),
+            ..Default::default()
+        };
         let distribution = Uniform::new(0, state.len()).expect("should always be able to create");
         for _ in 0..state.len() {
             let site_index = distribution.sample(rng);
-            let old_energy = hamiltonian.energy(thermostat, &state, site_index);
+            let old_energy = hamiltonian.energy(thermostat, state, site_index);
             let old_spin = state.at(site_index).clone();
             state.set_at(site_index, Spin::rand(rng));
-            let new_energy = hamiltonian.energy(thermostat, &state, site_index);
+            let new_energy = hamiltonian.energy(thermostat, state, site_index);
             let delta = new_energy - old_energy;
-            if delta < 0.0 {
-                continue;
-            }
-            if rng.random::<f64>() < (-delta / thermostat.temperature()).exp() {
+            if delta < 0.0 || rng.random::<f64>() < (-delta / thermostat.temperature()).exp() {
+                report.replace(delta, &old_spin, state.at(site_index));
                 continue;
             }
             state.set_at(site_index, old_spin);
         }
-        state
+        report
     }
 }
 
@@ -96,6 +150,10 @@ impl<S: Spin> Integrator<S> for MetropolisIntegrator {
 /// The Metropolis integrator is a Monte Carlo method that allows you to sample
 /// the phase space of a system. It is based on the Metropolis algorithm, which
 /// is a Markov Chain Monte Carlo method.
+///
+/// The only move it proposes is `Spin::flip`, so it samples unit Ising spins
+/// but not spins with more states, such as clock or Potts spins, which need
+/// `MetropolisIntegrator`.
 #[derive(Debug, Default)]
 pub struct MetropolisFlipIntegrator {}
 
@@ -111,29 +169,31 @@ where
     S: Spin,
 {
     fn step<R: Rng, H: Hamiltonian<S>>(
-        &self,
+        &mut self,
         rng: &mut R,
         thermostat: &Thermostat<S>,
         hamiltonian: &H,
-        mut state: State<S>,
-    ) -> State<S> {
+        state: &mut State<S>,
+    ) -> StepReport {
+        let mut report = StepReport {
+            proposed: state.len(),
+            ..Default::default()
+        };
         let sites = Uniform::new(0, state.len()).expect("should always be able to create");
         for _ in 0..state.len() {
             let site = sites.sample(rng);
-            let old_energy = hamiltonian.energy(thermostat, &state, site);
+            let old_energy = hamiltonian.energy(thermostat, state, site);
             let old_spin = state.at(site).clone();
             state.set_at(site, old_spin.flip());
-            let new_energy = hamiltonian.energy(thermostat, &state, site);
+            let new_energy = hamiltonian.energy(thermostat, state, site);
             let delta = new_energy - old_energy;
-            if delta < 0.0 {
-                continue;
-            }
-            if rng.random::<f64>() < (-delta / thermostat.temperature()).exp() {
+            if delta < 0.0 || rng.random::<f64>() < (-delta / thermostat.temperature()).exp() {
+                report.replace(delta, &old_spin, state.at(site));
                 continue;
             }
             state.set_at(site, old_spin);
         }
-        state
+        report
     }
 }
 
@@ -142,18 +202,28 @@ where
 /// The Wolff integrator is a Monte Carlo method that allows you to sample
 /// the phase space of a system using cluster updates. It is based on the
 /// Wolff algorithm, which is a cluster Monte Carlo method.
+///
+/// The cluster is built using scratch buffers owned by the integrator, so
+/// steps don't allocate once the buffers reached the size of the sample.
 #[derive(Debug)]
 pub struct WolffIntegrator {
     exchange: f64,
     neighbor_list: Vec<Vec<usize>>,
+    visited: Vec<bool>,
+    queue: VecDeque<usize>,
+    cluster: Vec<usize>,
 }
 
 impl WolffIntegrator {
     /// Create a new Wolff integrator with a given neighbor list.
     pub fn new(exchange: f64, neighbor_list: Vec<Vec<usize>>) -> Self {
+        let n = neighbor_list.len();
         Self {
             exchange,
             neighbor_list,
+            visited: vec![false; n],
+            queue: VecDeque::with_capacity(n),
+            cluster: Vec::with_capacity(n),
         }
     }
 
@@ -164,26 +234,25 @@ impl WolffIntegrator {
             neighbor_list[edge.source()].push(edge.target());
             neighbor_list[edge.target()].push(edge.source());
         }
-        Self {
-            exchange,
-            neighbor_list,
-        }
+        Self::new(exchange, neighbor_list)
     }
 }
 
 impl Integrator<IsingSpin> for WolffIntegrator {
     /// Perform a single step of the Wolff integrator.
     ///
-    /// Even though the Hamiltonian is not used in this integrator, it is included
-    /// in the function signature to comply with the `Integrator` trait. This method
-    /// is only valid for Ising spins and the Exchange Hamiltonian.
+    /// The Hamiltonian is not used to build the cluster, only to report the
+    /// energy change of the flip. Bonds inside the cluster don't change when
+    /// it flips, so the change is the sum of the site energy changes of the
+    /// cluster. This method is only valid for Ising spins and the Exchange
+    /// Hamiltonian.
     fn step<R: Rng, H: Hamiltonian<IsingSpin>>(
-        &self,
+        &mut self,
         rng: &mut R,
         thermostat: &Thermostat<IsingSpin>,
-        _hamiltonian: &H,
-        state: State<IsingSpin>,
-    ) -> State<IsingSpin> {
+        hamiltonian: &H,
+        state: &mut State<IsingSpin>,
+    ) -> StepReport {
         // Make sure the neighbor list matches the state size
         debug_assert!(state.len() == self.neighbor_list.len());
 
@@ -192,7 +261,7 @@ impl Integrator<IsingSpin> for WolffIntegrator {
         let source = sites.sample(rng);
 
         // Determine the reference spin and the probability to add neighbors
-        let reference = state.at(source);
+        let reference = state.at(source).clone();
         let prob = if thermostat.temperature() > 0.0 {
             1.0 - (-2.0 * self.exchange / thermostat.temperature()).exp()
         } else {
@@ -200,31 +269,53 @@ impl Integrator<IsingSpin> for WolffIntegrator {
         };
 
         // Build the cluster using a queue
-        let mut queue = VecDeque::new();
-        queue.push_back(source);
-        let mut visited = vec![false; state.len()];
-        visited[source] = true;
-        while let Some(site) = queue.pop_front() {
+        self.queue.clear();
+        self.cluster.clear();
+        self.queue.push_back(source);
+        self.visited[source] = true;
+        self.cluster.push(source);
+        while let Some(site) = self.queue.pop_front() {
             for &neighbor in &self.neighbor_list[site] {
-                if !visited[neighbor]
-                    && state.at(neighbor) == reference
+                if !self.visited[neighbor]
+                    && *state.at(neighbor) == reference
                     && rng.random::<f64>() < prob
                 {
-                    queue.push_back(neighbor);
-                    visited[neighbor] = true;
+                    self.queue.push_back(neighbor);
+                    self.visited[neighbor] = true;
+                    self.cluster.push(neighbor);
                 }
             }
         }
 
         // Flip the spins in the cluster
-        state
-            .into_iter()
-            .zip(visited.iter())
-            .map(
-                |(spin, &in_cluster)| {
-                    if in_cluster { spin.flip() } else { spin }
-                },
-            )
-            .collect()
+        let mut report = StepReport {
+            proposed: 1,
+            accepted: 1,
+            flipped: self.cluster.len(),
+            ..Default::default()
+        };
+        let old_energy: f64 = self
+            .cluster
+            .iter()
+            .map(|&site| hamiltonian.energy(thermostat, state, site))
+            .sum();
+        let flipped = reference.flip();
+        for &site in &self.cluster {
+            state.set_at(site, flipped.clone());
+            self.visited[site] = false;
+        }
+        let new_energy: f64 = self
+            .cluster
+            .iter()
+            .map(|&site| hamiltonian.energy(thermostat, state, site))
+            .sum();
+        report.energy = new_energy - old_energy;
+        let size = self.cluster.len() as f64;
+        report.magnetization = [
+            -2.0 * size * reference.sx(),
+            -2.0 * size * reference.sy(),
+            -2.0 * size * reference.sz(),
+        ];
+        report
     }
 }
@@ -35,6 +35,17 @@
 //! in an Ising model. The `IsingSpin` type implemented as an enum that can take
 //! the up or down variants.
 //!
+//! Planar models are covered by `XYSpin`, a unit vector in the xy plane, and
+//! the discrete `ClockSpin` and `PottsSpin` types, which take one of `Q`
+//! states given as a const generic parameter.
+//!
+//! Spins longer than one are available as `IsingSpinS` and `HeisenbergSpinS`,
+//! their length `S` is half the const generic parameter, so half integer
+//! spins are supported. `BlumeCapelSpin` is the spin one Ising spin.
+//!
+//! `SpinArrays` stores the projections of a state as one array per axis,
+//! which `FixedExchange` can sweep instead of the spins themselves.
+//!
 //! ## Hamiltonians
 //!
 //! A hamiltonian is a function that calculates the energy of a spin system.
@@ -45,10 +56,13 @@
 //! Among others this library provides the following hamiltonians:
 //!
 //! * `Exchange` - A hamiltonian that calculates the exchange energy of a spin system.
+//! * `FixedExchange` - An exchange hamiltonian for lattices with a fixed coordination number.
 //! * `Gauge` - A hamiltonian that calculates the gauge energy of a spin system.
 //! * `UniaxialAnisotropy` - A hamiltonian that calculates the uniaxial anisotropy energy of a spin system.
+//! * `SingleIonAnisotropy` - A hamiltonian that calculates the `D Sz²` anisotropy of the Blume-Capel model.
 //! * `Zeeman` - A hamiltonian that calculates the Zeeman energy of a spin system.
 //! * `Compound` - A hamiltonian that combines multiple hamiltonians.
+//! * `Named` - A hamiltonian that reports another one under a custom name.
 //!
 //! ## Instruments
 //!
@@ -60,6 +74,9 @@
 //! * `StatSensor` - An instrument that measures the statistical properties of the spin system.
 //! * `ObservableSensor` - An instrument that measures the observables of the spin system.
 //! * `StateSensor` - An instrument that writes the state of the spin system.
+//! * `CorrelationSensor` - An instrument that measures the structure factor and pair correlation.
+//! * `SnapshotSensor` - An instrument that writes VTK or extended XYZ snapshots for visualization.
+//! * `TrajectorySensor` - An instrument that writes spin states to an indexed binary trajectory.
 //!
 //! ## Machine
 //!
@@ -90,6 +107,8 @@
 //! * `Relax` - A program that relaxes the system at a specified temperature for a given number of steps.
 //! * `CoolDown` - A program that gradually cools down the system from a maximum temperature to a minimum temperature over a series of steps.
 //! * `HysteresisLoop` - A program that simulates a hysteresis loop by varying the external magnetic field and measuring the system's response.
+//! * `Forc` - A program that measures first-order reversal curves along a given field direction.
+//! * `Schedule` - A program that follows explicit temperature and field points, a CSV file or a ramp.
 //!
 //! # Example
 //!
@@ -128,13 +147,18 @@
 pub mod energy;
 
 pub mod accumulator;
+pub mod analysis;
 pub mod error;
+pub mod geometry;
 pub mod input;
 pub mod instrument;
 pub mod integrator;
 pub mod machine;
+pub mod observable;
 pub mod output;
 pub mod program;
+pub mod snapshot;
 pub mod state;
 pub mod thermostat;
+pub mod trajectory;
 pub mod util;
@@ -31,14 +31,149 @@
 //! ```
 
 use crate::{
+    accumulator::Accumulator,
     energy::Hamiltonian,
-    error::MachineResult,
+    error::{MachineError, MachineResult},
     instrument::Instrument,
-    integrator::Integrator,
-    state::{Spin, State},
+    integrator::{Integrator, StepReport},
+    state::{Field, Spin, State},
     thermostat::Thermostat,
 };
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Running totals of the energy and magnetization of the sample.
+///
+/// The machine keeps them up to date from the changes reported by the
+/// integrator, so instruments don't need to recompute them every step. The
+/// report of the last step is kept for its move statistics.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Totals {
+    energy: f64,
+    magnetization: [f64; 3],
+    last_step: StepReport,
+}
+
+impl Totals {
+    /// Compute the totals of a state from scratch.
+    pub fn of<H: Hamiltonian<S>, S: Spin>(
+        thermostat: &Thermostat<S>,
+        hamiltonian: &H,
+        state: &State<S>,
+    ) -> Self {
+        let magnetization = state.spins().iter().fold([0.0; 3], |m, s| {
+            [m[0] + s.sx(), m[1] + s.sy(), m[2] + s.sz()]
+        });
+        Self {
+            energy: hamiltonian.total_energy(thermostat, state),
+            magnetization,
+            last_step: StepReport::default(),
+        }
+    }
+
+    /// Total energy.
+    pub fn energy(&self) -> f64 {
+        self.energy
+    }
+
+    /// Components of the total magnetization.
+    pub fn magnetization_vector(&self) -> [f64; 3] {
+        self.magnetization
+    }
+
+    /// Total magnetization.
+    pub fn magnetization<S: Spin>(&self) -> Field<S> {
+        let [mx, my, mz] = self.magnetization;
+        S::from_projections(mx, my, mz)
+    }
+
+    /// Report of the last step.
+    pub fn last_step(&self) -> &StepReport {
+        &self.last_step
+    }
+
+    fn apply(&mut self, report: StepReport) {
+        self.energy += report.energy;
+        for (m, dm) in self.magnetization.iter_mut().zip(report.magnetization) {
+            *m += dm;
+        }
+        self.last_step = report;
+    }
+
+    fn drift(&self, other: &Totals) -> f64 {
+        let magnetization = self
+            .magnetization
+            .iter()
+            .zip(other.magnetization)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        (self.energy - other.energy).abs().max(magnetization)
+    }
+}
+
+/// Summary of the energy and magnetization seen during a measurement.
+#[derive(Default)]
+pub struct Summary {
+    energy: Accumulator,
+    magnetization: Accumulator,
+    temperature: f64,
+    n: usize,
+}
+
+impl Summary {
+    /// Collect the energy and magnetization of a state.
+    fn collect<S: Spin>(&mut self, thermostat: &Thermostat<S>, state: &State<S>, totals: &Totals) {
+        self.temperature = thermostat.temperature();
+        self.n = state.len();
+        self.energy.collect(totals.energy());
+        self.magnetization
+            .collect(totals.magnetization::<S>().magnitude());
+    }
+
+    /// Accumulated energies.
+    pub fn energy(&self) -> &Accumulator {
+        &self.energy
+    }
+
+    /// Accumulated magnetization magnitudes.
+    pub fn magnetization(&self) -> &Accumulator {
+        &self.magnetization
+    }
+
+    /// Specific heat per spin.
+    pub fn specific_heat(&self) -> f64 {
+        self.energy.variance() / (self.n as f64 * self.temperature.powi(2))
+    }
+
+    /// Magnetic susceptibility per spin.
+    pub fn susceptibility(&self) -> f64 {
+        self.magnetization.variance() / (self.n as f64 * self.temperature)
+    }
+}
+
+/// Criterion to stop relaxing once the energy stops drifting.
+///
+/// The energy is averaged over consecutive blocks of `block` steps, the
+/// sample is considered equilibrated when the mean energy per spin of two
+/// consecutive blocks differs by less than `tolerance`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Equilibration {
+    block: usize,
+    tolerance: f64,
+}
+
+impl Equilibration {
+    /// Create a new equilibration criterion.
+    pub fn new(block: usize, tolerance: f64) -> Self {
+        Self { block, tolerance }
+    }
+}
+
+impl Default for Equilibration {
+    fn default() -> Self {
+        Self::new(100, 1e-3)
+    }
+}
 
 /// A box containing the sample with a given temperature and field.
 pub struct Machine<H, I, S>
@@ -52,8 +187,14 @@ where
     integrator: I,
     instruments: Vec<Box<dyn Instrument<H, S>>>,
     state: State<S>,
+    totals: Totals,
+    verify_every: Option<usize>,
+    steps: usize,
 }
 
+/// Tolerance of the running totals against a full recompute.
+const DRIFT_TOLERANCE: f64 = 1e-6;
+
 impl<H, I, S> Machine<H, I, S>
 where
     H: Hamiltonian<S>,
@@ -68,15 +209,31 @@ where
         instruments: Vec<Box<dyn Instrument<H, S>>>,
         state: State<S>,
     ) -> Self {
+        let totals = Totals::of(&thermostat, &hamiltonian, &state);
         Machine {
             thermostat,
             hamiltonian,
             integrator,
             instruments,
             state,
+            totals,
+            verify_every: None,
+            steps: 0,
         }
     }
 
+    /// Verify the running totals against a full recompute every given
+    /// number of steps.
+    pub fn set_verify_every(mut self, steps: Option<usize>) -> Self {
+        self.verify_every = steps.filter(|&steps| steps > 0);
+        self
+    }
+
+    /// Get the running totals of the machine.
+    pub fn totals(&self) -> &Totals {
+        &self.totals
+    }
+
     /// Get the current thermostat of the machine.
     pub fn thermostat(&self) -> &Thermostat<S> {
         &self.thermostat
@@ -85,17 +242,54 @@ where
     /// Set the thermostat of the machine.
     pub fn set_thermostat(&mut self, thermostat: Thermostat<S>) {
         self.thermostat = thermostat;
+        self.totals = Totals::of(&self.thermostat, &self.hamiltonian, &self.state);
+    }
+
+    /// Compare the running totals with a full recompute and resynchronize.
+    fn verify(&mut self) -> MachineResult<()> {
+        let actual = Totals::of(&self.thermostat, &self.hamiltonian, &self.state);
+        let drift = self.totals.drift(&actual);
+        if drift > DRIFT_TOLERANCE * actual.energy.abs().max(self.state.len() as f64) {
+            return Err(MachineError::TotalsDrift {
+                step: self.steps,
+                drift,
+            });
+        }
+        self.totals.energy = actual.energy;
+        self.totals.magnetization = actual.magnetization;
+        Ok(())
+    }
+
+    /// Run and observe the machine for a single step.
+    fn step<R: Rng>(&mut self, rng: &mut R, summary: Option<&mut Summary>) -> MachineResult<()> {
+        let report =
+            self.integrator
+                .step(rng, &self.thermostat, &self.hamiltonian, &mut self.state);
+        self.totals.apply(report);
+        self.steps += 1;
+        if let Some(every) = self.verify_every
+            && self.steps.is_multiple_of(every)
+        {
+            self.verify()?;
+        }
+        if let Some(summary) = summary {
+            summary.collect(&self.thermostat, &self.state, &self.totals);
+        }
+        for instrument in self.instruments.iter_mut() {
+            instrument.after_step(&self.state, &self.totals)?;
+        }
+        Ok(())
     }
 
     /// Run and observe the machine for a given number of steps.
-    fn run<R: Rng>(&mut self, rng: &mut R, steps: usize) -> MachineResult<()> {
+    fn run<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        steps: usize,
+        mut summary: Option<&mut Summary>,
+    ) -> MachineResult<()> {
         for _ in 0..steps {
-            self.state =
-                self.integrator
-                    .step(rng, &self.thermostat, &self.hamiltonian, self.state.clone());
-            for instrument in self.instruments.iter_mut() {
-                instrument.after_step(&self.state)?;
-            }
+            self.step(rng, summary.as_deref_mut())?;
         }
         Ok(())
     }
@@ -105,22 +299,188 @@ where
         for instrument in self.instruments.iter_mut() {
             instrument.on_relax_start(&self.thermostat, &self.hamiltonian, &self.state)?;
         }
-        self.run(rng, steps)?;
+        self.run(rng, steps, None)?;
         for instrument in self.instruments.iter_mut() {
             instrument.on_relax_end()?;
         }
         Ok(())
     }
 
+    /// Relax the machine until it is equilibrated or `max_steps` are done.
+    ///
+    /// Returns the number of steps used.
+    pub fn relax_until<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        equilibration: &Equilibration,
+        max_steps: usize,
+    ) -> MachineResult<usize> {
+        for instrument in self.instruments.iter_mut() {
+            instrument.on_relax_start(&self.thermostat, &self.hamiltonian, &self.state)?;
+        }
+        let block = equilibration.block.max(1);
+        let mut previous: Option<f64> = None;
+        let mut current = 0.0;
+        let mut steps = 0;
+        while steps < max_steps {
+            self.step(rng, None)?;
+            steps += 1;
+            current += self.totals.energy();
+            if steps % block != 0 {
+                continue;
+            }
+            let mean = current / (block * self.state.len()) as f64;
+            current = 0.0;
+            if let Some(previous) = previous
+                && (mean - previous).abs() < equilibration.tolerance
+            {
+                for instrument in self.instruments.iter_mut() {
+                    instrument.on_equilibrated(steps)?;
+                }
+                break;
+            }
+            previous = Some(mean);
+        }
+        for instrument in self.instruments.iter_mut() {
+            instrument.on_relax_end()?;
+        }
+        Ok(steps)
+    }
+
+    /// Relax the machine for at most `steps` steps, stopping early when an
+    /// equilibration criterion is given and met.
+    ///
+    /// Returns the number of steps used.
+    pub fn relax_with<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        steps: usize,
+        equilibration: Option<&Equilibration>,
+    ) -> MachineResult<usize> {
+        match equilibration {
+            Some(equilibration) => self.relax_until(rng, equilibration, steps),
+            None => self.relax_for(rng, steps).map(|_| steps),
+        }
+    }
+
     /// Measure the machine for a given number of steps.
     pub fn measure_for<R: Rng>(&mut self, rng: &mut R, steps: usize) -> MachineResult<()> {
+        self.measure(rng, steps, None)
+    }
+
+    /// Measure the machine for a given number of steps and summarize the
+    /// energy and magnetization seen during the measurement.
+    pub fn measure_and_summarize_for<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        steps: usize,
+    ) -> MachineResult<Summary> {
+        let mut summary = Summary::default();
+        self.measure(rng, steps, Some(&mut summary))?;
+        Ok(summary)
+    }
+
+    fn measure<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        steps: usize,
+        summary: Option<&mut Summary>,
+    ) -> MachineResult<()> {
         for instrument in self.instruments.iter_mut() {
             instrument.on_measure_start(&self.thermostat, &self.hamiltonian, &self.state)?;
         }
-        self.run(rng, steps)?;
+        self.run(rng, steps, summary)?;
         for instrument in self.instruments.iter_mut() {
             instrument.on_measure_end()?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        energy::{Exchange, Zeeman},
+        error::InstrumentResult,
+        integrator::{MetropolisFlipIntegrator, MetropolisIntegrator, WolffIntegrator},
+        state::{HeisenbergSpin, IsingSpin},
+    };
+    use rand::SeedableRng;
+    use rand_pcg::Pcg64;
+    use std::{cell::RefCell, rc::Rc};
+    use vegas_lattice::Lattice;
+
+    #[test]
+    fn running_totals_match_a_full_recompute() {
+        let mut rng = Pcg64::seed_from_u64(7);
+        let lattice = Lattice::sc(1.0).expand(6, 6, 1).drop_z();
+        let hamiltonian = hamiltonian!(Exchange::from_lattice(1.0, &lattice), Zeeman::new());
+        let state = State::<IsingSpin>::rand_with_size(&mut rng, lattice.sites().len());
+        let thermostat = Thermostat::new(2.0, Field::new(IsingSpin::Up, 0.3));
+        let mut machine = Machine::new(
+            thermostat.clone(),
+            hamiltonian.clone(),
+            MetropolisFlipIntegrator::new(),
+            Vec::new(),
+            state.clone(),
+        )
+        .set_verify_every(Some(1));
+        machine.relax_for(&mut rng, 50).unwrap();
+        let mut machine = Machine::new(
+            thermostat,
+            hamiltonian,
+            WolffIntegrator::from_lattice(1.0, &lattice),
+            Vec::new(),
+            state,
+        )
+        .set_verify_every(Some(1));
+        machine.relax_for(&mut rng, 50).unwrap();
+        let moves = machine.totals().last_step();
+        assert_eq!(moves.acceptance(), 1.0);
+        assert!(moves.mean_cluster_size() >= 1.0);
+
+        let state = State::<HeisenbergSpin>::rand_with_size(&mut rng, lattice.sites().len());
+        let thermostat = Thermostat::new(1.0, Field::new(HeisenbergSpin::up(), 0.3));
+        let mut machine = Machine::new(
+            thermostat,
+            hamiltonian!(Exchange::from_lattice(1.0, &lattice), Zeeman::new()),
+            MetropolisIntegrator::new(),
+            Vec::new(),
+            state,
+        )
+        .set_verify_every(Some(1));
+        machine.relax_for(&mut rng, 50).unwrap();
+    }
+
+    /// Records the steps reported by every equilibrated relaxation.
+    #[derive(Clone, Default)]
+    struct EquilibrationLog(Rc<RefCell<Vec<usize>>>);
+
+    impl<H: Hamiltonian<S>, S: Spin> Instrument<H, S> for EquilibrationLog {
+        fn on_equilibrated(&mut self, steps: usize) -> InstrumentResult<()> {
+            self.0.borrow_mut().push(steps);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn relaxation_stops_once_the_energy_settles() {
+        let mut rng = Pcg64::seed_from_u64(7);
+        let lattice = Lattice::sc(1.0).expand(4, 4, 1).drop_z();
+        let log = EquilibrationLog::default();
+        let mut machine = Machine::new(
+            Thermostat::near_zero(),
+            Exchange::from_lattice(1.0, &lattice),
+            MetropolisFlipIntegrator::new(),
+            vec![Box::new(log.clone())],
+            State::<IsingSpin>::up_with_size(lattice.sites().len()),
+        );
+        let settled = Equilibration::new(10, 1e-3);
+        assert_eq!(machine.relax_until(&mut rng, &settled, 1000).unwrap(), 20);
+        assert_eq!(*log.0.borrow(), vec![20]);
+        let strict = Equilibration::new(10, 0.0);
+        assert_eq!(machine.relax_until(&mut rng, &strict, 1000).unwrap(), 1000);
+        assert_eq!(*log.0.borrow(), vec![20]);
+    }
+}
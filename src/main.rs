@@ -1,60 +1,165 @@
 //! A command line interface for running Vegas simulations and benchmarks.
 
-use clap::{Parser, Subcommand};
-use rand::SeedableRng;
+use clap::{Parser, Subcommand, ValueEnum};
+use rand::{Rng, SeedableRng};
 use rand_pcg::Pcg64;
 use std::{
     fs::File,
+    hint::black_box,
     io::{Read, stdin, stdout},
     path::PathBuf,
+    time::Instant,
 };
 use vegas::{
-    energy::Exchange,
-    error::{IoError, VegasResult},
+    analysis::{analyze_observables, write_csv},
+    energy::{Exchange, FixedExchange, Hamiltonian},
+    error::{IoError, VegasError, VegasResult},
     input::{Input, Model},
     instrument::{Instrument, StatSensor},
     integrator::MetropolisIntegrator,
     machine::Machine,
+    output::{Metadata, SummaryParquetOutput, WithMetadata},
     program::{CoolDown, Program},
-    state::{Field, HeisenbergSpin, IsingSpin, State},
+    state::{
+        BlumeCapelSpin, ClockSpin, Field, HeisenbergSpin, IsingSpin, PottsSpin, Spin, SpinArrays,
+        State, XYSpin,
+    },
     thermostat::Thermostat,
 };
 use vegas_lattice::Lattice;
 
-fn bench_model(model: Model, length: usize, seed: Option<u64>) -> VegasResult<()> {
-    let lattice = Lattice::sc(1.0).expand_all(length);
-    let hamiltonian = Exchange::from_lattice(1.0, &lattice);
-    let mut rng = match seed {
-        Some(s) => Pcg64::seed_from_u64(s),
-        None => Pcg64::from_rng(&mut rand::rng()),
-    };
-    match model {
-        Model::Ising => {
-            let program = CoolDown::default()
-                .set_max_temperature(5.0)
-                .set_cool_rate(0.05);
-            let state = State::<IsingSpin>::rand_with_size(&mut rng, lattice.sites().len());
-            let integrator = MetropolisIntegrator::new();
-            let thermostat = Thermostat::new(2.8, Field::zero());
-            let instruments: Vec<Box<dyn Instrument<_, _>>> =
-                vec![Box::new(StatSensor::<_, _>::new(Box::new(stdout())))];
-            let mut machine = Machine::new(thermostat, hamiltonian, integrator, instruments, state);
-            program.run(&mut rng, &mut machine)?;
-            Ok(())
+/// Exchange kernels compared in benchmarks.
+#[derive(Debug, Clone, Default, ValueEnum)]
+enum Kernel {
+    /// Sparse matrix exchange, works for any lattice
+    Sparse,
+    /// Flat neighbor table exchange, for fixed coordination lattices
+    Fixed,
+    /// Run both kernels from the same seed and report the speedup
+    #[default]
+    Both,
+    /// Time the fixed exchange energies over the spins of a state and over
+    /// per axis spin arrays
+    Layout,
+}
+
+/// Number of energy sweeps timed by the layout benchmark.
+const LAYOUT_SWEEPS: usize = 200;
+
+/// Time energy sweeps of the fixed exchange over both spin layouts.
+///
+/// A sweep computes the energy of every site and the total energy.
+fn bench_layout<S: Spin>(seed: u64, lattice: &Lattice) -> VegasResult<()> {
+    let mut rng = Pcg64::seed_from_u64(seed);
+    let state = State::<S>::rand_with_size(&mut rng, lattice.sites().len());
+    let arrays = SpinArrays::from_state(&state);
+    let thermostat = Thermostat::new(2.8, Field::zero());
+    let hamiltonian = FixedExchange::from_lattice(1.0, lattice)
+        .expect("periodic cubic lattices have fixed coordination");
+    let start = Instant::now();
+    for _ in 0..LAYOUT_SWEEPS {
+        for index in 0..state.len() {
+            black_box(hamiltonian.energy(&thermostat, &state, index));
         }
-        Model::Heisenberg => {
-            let program = CoolDown::default()
-                .set_max_temperature(2.5)
-                .set_cool_rate(0.05);
-            let state = State::<HeisenbergSpin>::rand_with_size(&mut rng, lattice.sites().len());
-            let integrator = MetropolisIntegrator::new();
-            let thermostat = Thermostat::new(2.8, Field::zero());
-            let instruments: Vec<Box<dyn Instrument<_, _>>> =
-                vec![Box::new(StatSensor::<_, _>::new(Box::new(stdout())))];
-            let mut machine = Machine::new(thermostat, hamiltonian, integrator, instruments, state);
-            program.run(&mut rng, &mut machine)?;
-            Ok(())
+        black_box(hamiltonian.total_energy(&thermostat, &state));
+    }
+    let spins = start.elapsed().as_secs_f64();
+    let start = Instant::now();
+    for _ in 0..LAYOUT_SWEEPS {
+        for index in 0..arrays.len() {
+            black_box(hamiltonian.site_energy_of(&arrays, index));
         }
+        black_box(hamiltonian.total_energy_of(&arrays));
+    }
+    let per_axis = start.elapsed().as_secs_f64();
+    eprintln!(
+        "{} spins, {} sweeps, spins {:.3}s, arrays {:.3}s, speedup {:.2}x",
+        state.len(),
+        LAYOUT_SWEEPS,
+        spins,
+        per_axis,
+        spins / per_axis
+    );
+    Ok(())
+}
+
+/// Run a cool down and return the elapsed time in seconds.
+fn bench_machine<H, S>(
+    seed: u64,
+    hamiltonian: H,
+    max_temperature: f64,
+    size: usize,
+) -> VegasResult<f64>
+where
+    H: Hamiltonian<S> + 'static,
+    S: Spin + 'static,
+{
+    let mut rng = Pcg64::seed_from_u64(seed);
+    let program = CoolDown::default()
+        .set_max_temperature(max_temperature)
+        .set_cool_rate(0.05);
+    let state = State::<S>::rand_with_size(&mut rng, size);
+    let integrator = MetropolisIntegrator::new();
+    let thermostat = Thermostat::new(2.8, Field::zero());
+    let instruments: Vec<Box<dyn Instrument<_, _>>> =
+        vec![Box::new(StatSensor::<_, _>::new(Box::new(stdout())))];
+    let mut machine = Machine::new(thermostat, hamiltonian, integrator, instruments, state);
+    let start = Instant::now();
+    program.run(&mut rng, &mut machine)?;
+    Ok(start.elapsed().as_secs_f64())
+}
+
+fn bench_kernels<S: Spin + 'static>(
+    seed: u64,
+    lattice: &Lattice,
+    max_temperature: f64,
+    kernel: Kernel,
+) -> VegasResult<()> {
+    let size = lattice.sites().len();
+    let sparse = || {
+        bench_machine::<_, S>(
+            seed,
+            Exchange::from_lattice(1.0, lattice),
+            max_temperature,
+            size,
+        )
+    };
+    let fixed = || {
+        let hamiltonian = FixedExchange::from_lattice(1.0, lattice)
+            .expect("periodic cubic lattices have fixed coordination");
+        bench_machine::<_, S>(seed, hamiltonian, max_temperature, size)
+    };
+    match kernel {
+        Kernel::Sparse => eprintln!("{} spins, sparse {:.3}s", size, sparse()?),
+        Kernel::Fixed => eprintln!("{} spins, fixed {:.3}s", size, fixed()?),
+        Kernel::Layout => bench_layout::<S>(seed, lattice)?,
+        Kernel::Both => {
+            let sparse = sparse()?;
+            let fixed = fixed()?;
+            eprintln!(
+                "{} spins, sparse {:.3}s, fixed {:.3}s, speedup {:.2}x",
+                size,
+                sparse,
+                fixed,
+                sparse / fixed
+            );
+        }
+    }
+    Ok(())
+}
+
+fn bench_model(model: Model, length: usize, kernel: Kernel, seed: Option<u64>) -> VegasResult<()> {
+    let lattice = Lattice::sc(1.0).expand_all(length);
+    let seed = seed.unwrap_or_else(|| rand::rng().random());
+    match model {
+        Model::Ising => bench_kernels::<IsingSpin>(seed, &lattice, 5.0, kernel),
+        Model::BlumeCapel => bench_kernels::<BlumeCapelSpin>(seed, &lattice, 5.0, kernel),
+        Model::Heisenberg => bench_kernels::<HeisenbergSpin>(seed, &lattice, 2.5, kernel),
+        Model::Xy => bench_kernels::<XYSpin>(seed, &lattice, 2.5, kernel),
+        Model::Clock => bench_kernels::<ClockSpin<6>>(seed, &lattice, 2.5, kernel),
+        // The dot product of Potts spins is not the one of their projections.
+        Model::Potts if matches!(kernel, Kernel::Layout) => Err(VegasError::NotImplementedError),
+        Model::Potts => bench_kernels::<PottsSpin<3>>(seed, &lattice, 2.0, kernel),
     }
 }
 
@@ -67,13 +172,34 @@ fn run_input(input: PathBuf, seed: Option<u64>) -> VegasResult<()> {
         file.read_to_string(&mut data).map_err(IoError::from)?;
     };
     let input: Input = toml::from_str(&data)?;
-    let mut rng = match seed {
-        Some(s) => Pcg64::seed_from_u64(s),
-        None => Pcg64::from_rng(&mut rand::rng()),
-    };
+    // Always run with a known seed so that it can be recorded in the outputs,
+    // TOML integers are signed so generated seeds stay in that range.
+    let seed = seed
+        .or(input.seed())
+        .unwrap_or_else(|| rand::rng().random_range(0..i64::MAX as u64));
+    let input = input.set_seed(seed);
+    let mut rng = Pcg64::seed_from_u64(seed);
     input.run(&mut rng)
 }
 
+fn analyze(input: PathBuf, output: Option<PathBuf>) -> VegasResult<()> {
+    let summaries = analyze_observables(&input)?;
+    match output {
+        Some(path) if path.extension().is_some_and(|ext| ext == "parquet") => {
+            // Keep the input, version and seed of the run with its summary.
+            let mut summary = SummaryParquetOutput::try_new(path)?;
+            summary.set_metadata(&Metadata::read(&input)?);
+            summary.write(&summaries)?;
+        }
+        Some(path) => {
+            let mut file = File::create(path).map_err(IoError::from)?;
+            write_csv(&mut file, &summaries)?;
+        }
+        None => write_csv(&mut stdout(), &summaries)?,
+    }
+    Ok(())
+}
+
 fn print_default_input() -> VegasResult<()> {
     let input = Input::default();
     let input = toml::to_string_pretty(&input)?;
@@ -98,6 +224,9 @@ enum SubCommand {
         model: Model,
         /// Length of the side lattice
         length: usize,
+        /// Exchange kernels to time
+        #[arg(short, long, value_enum, default_value_t)]
+        kernel: Kernel,
         /// Seed for RNG, random if omitted
         #[arg(short, long)]
         seed: Option<u64>,
@@ -106,10 +235,20 @@ enum SubCommand {
     Run {
         /// Input file, or "-" for stdin
         input: PathBuf,
-        /// Seed for RNG, random if omitted
+        /// Seed for RNG, overrides the seed of the input, random if both are
+        /// omitted
         #[arg(short, long)]
         seed: Option<u64>,
     },
+    /// Summarise the measurement stages of an observables file
+    Analyze {
+        /// Observables parquet file
+        input: PathBuf,
+        /// Output file, parquet if it ends in ".parquet" and CSV otherwise,
+        /// CSV to stdout if omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 #[derive(Debug, Parser)]
@@ -126,8 +265,10 @@ fn main() {
         SubCommand::Bench {
             length,
             model,
+            kernel,
             seed,
-        } => check_error(bench_model(model, length, seed)),
+        } => check_error(bench_model(model, length, kernel, seed)),
         SubCommand::Run { input, seed } => check_error(run_input(input, seed)),
+        SubCommand::Analyze { input, output } => check_error(analyze(input, output)),
     }
 }
@@ -0,0 +1,329 @@
+//! Observables recorded by the `ObservableSensor`.
+//!
+//! An observable is a function of the state, the thermostat and the
+//! hamiltonian that produces one or more named values per step. The sensor
+//! writes one column per value, so the observables file only contains the
+//! quantities that were asked for.
+
+use crate::{
+    energy::Hamiltonian,
+    geometry::triangulate,
+    machine::Totals,
+    state::{Spin, State},
+    thermostat::Thermostat,
+};
+use vegas_lattice::Lattice;
+
+/// A quantity measured after every step.
+pub trait Observable<H, S>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    /// Names of the columns written by the observable.
+    fn columns(&self) -> Vec<String>;
+
+    /// Measure the observable, pushing one value per column.
+    ///
+    /// The running totals of the machine are given so that observables
+    /// derived from the energy or the magnetization are cheap.
+    fn measure(
+        &self,
+        thermostat: &Thermostat<S>,
+        hamiltonian: &H,
+        state: &State<S>,
+        totals: &Totals,
+        values: &mut Vec<f64>,
+    );
+}
+
+/// Total energy of the state.
+#[derive(Debug, Default)]
+pub struct Energy;
+
+impl<H, S> Observable<H, S> for Energy
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn columns(&self) -> Vec<String> {
+        vec!["energy".into()]
+    }
+
+    fn measure(
+        &self,
+        _thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        _state: &State<S>,
+        totals: &Totals,
+        values: &mut Vec<f64>,
+    ) {
+        values.push(totals.energy());
+    }
+}
+
+/// Energy of every component of the hamiltonian.
+///
+/// Columns are named after the components with an `energy_` prefix.
+#[derive(Debug)]
+pub struct EnergyComponents {
+    columns: Vec<String>,
+}
+
+impl EnergyComponents {
+    /// Create the observable for the components of the given hamiltonian.
+    pub fn new<H, S>(hamiltonian: &H) -> Self
+    where
+        H: Hamiltonian<S>,
+        S: Spin,
+    {
+        Self {
+            columns: hamiltonian
+                .component_names()
+                .iter()
+                .map(|name| format!("energy_{}", name))
+                .collect(),
+        }
+    }
+}
+
+impl<H, S> Observable<H, S> for EnergyComponents
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn columns(&self) -> Vec<String> {
+        self.columns.clone()
+    }
+
+    fn measure(
+        &self,
+        thermostat: &Thermostat<S>,
+        hamiltonian: &H,
+        state: &State<S>,
+        _totals: &Totals,
+        values: &mut Vec<f64>,
+    ) {
+        hamiltonian.energy_breakdown(thermostat, state, values);
+    }
+}
+
+/// Magnitude of the total magnetization.
+#[derive(Debug, Default)]
+pub struct Magnetization;
+
+impl<H, S> Observable<H, S> for Magnetization
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn columns(&self) -> Vec<String> {
+        vec!["magnetization".into()]
+    }
+
+    fn measure(
+        &self,
+        _thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        _state: &State<S>,
+        totals: &Totals,
+        values: &mut Vec<f64>,
+    ) {
+        values.push(totals.magnetization::<S>().magnitude());
+    }
+}
+
+/// Components of the total magnetization.
+#[derive(Debug, Default)]
+pub struct MagnetizationVector;
+
+impl<H, S> Observable<H, S> for MagnetizationVector
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn columns(&self) -> Vec<String> {
+        vec!["mx".into(), "my".into(), "mz".into()]
+    }
+
+    fn measure(
+        &self,
+        _thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        _state: &State<S>,
+        totals: &Totals,
+        values: &mut Vec<f64>,
+    ) {
+        values.extend(totals.magnetization_vector());
+    }
+}
+
+/// Total magnetization projected onto the field direction.
+#[derive(Debug, Default)]
+pub struct ParallelMagnetization;
+
+impl<H, S> Observable<H, S> for ParallelMagnetization
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn columns(&self) -> Vec<String> {
+        vec!["m_parallel".into()]
+    }
+
+    fn measure(
+        &self,
+        thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        _state: &State<S>,
+        totals: &Totals,
+        values: &mut Vec<f64>,
+    ) {
+        values.push(thermostat.field().project(&totals.magnetization()));
+    }
+}
+
+/// Topological charge over a triangulation of a planar lattice.
+#[derive(Debug)]
+pub struct TopologicalCharge {
+    triangles: Vec<[usize; 3]>,
+}
+
+impl TopologicalCharge {
+    pub fn new(triangles: Vec<[usize; 3]>) -> Self {
+        Self { triangles }
+    }
+
+    /// Triangulate the given planar lattice.
+    pub fn from_lattice(lattice: &Lattice) -> Self {
+        Self::new(triangulate(lattice))
+    }
+}
+
+impl<H, S> Observable<H, S> for TopologicalCharge
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn columns(&self) -> Vec<String> {
+        vec!["topological_charge".into()]
+    }
+
+    fn measure(
+        &self,
+        _thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        state: &State<S>,
+        _totals: &Totals,
+        values: &mut Vec<f64>,
+    ) {
+        values.push(state.topological_charge(&self.triangles));
+    }
+}
+
+/// Move statistics of the last step of the integrator.
+///
+/// Records the acceptance ratio, the mean number of spins changed per
+/// accepted move, which is the cluster size of cluster algorithms, and the
+/// fraction of the sample changed during the step.
+#[derive(Debug, Default)]
+pub struct Moves;
+
+impl<H, S> Observable<H, S> for Moves
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    fn columns(&self) -> Vec<String> {
+        vec![
+            "acceptance".into(),
+            "cluster_size".into(),
+            "flipped_fraction".into(),
+        ]
+    }
+
+    fn measure(
+        &self,
+        _thermostat: &Thermostat<S>,
+        _hamiltonian: &H,
+        state: &State<S>,
+        totals: &Totals,
+        values: &mut Vec<f64>,
+    ) {
+        let report = totals.last_step();
+        values.extend([
+            report.acceptance(),
+            report.mean_cluster_size(),
+            report.flipped as f64 / state.len() as f64,
+        ]);
+    }
+}
+
+/// Names of the built-in observables.
+pub const NAMES: [&str; 7] = [
+    "energy",
+    "energy_components",
+    "magnetization",
+    "magnetization_vector",
+    "m_parallel",
+    "topological_charge",
+    "moves",
+];
+
+/// Look up a built-in observable by name.
+pub fn by_name<H, S>(
+    name: &str,
+    hamiltonian: &H,
+    lattice: &Lattice,
+) -> Option<Box<dyn Observable<H, S>>>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    match name {
+        "energy" => Some(Box::new(Energy)),
+        "energy_components" => Some(Box::new(EnergyComponents::new(hamiltonian))),
+        "magnetization" => Some(Box::new(Magnetization)),
+        "magnetization_vector" => Some(Box::new(MagnetizationVector)),
+        "m_parallel" => Some(Box::new(ParallelMagnetization)),
+        "topological_charge" => Some(Box::new(TopologicalCharge::from_lattice(lattice))),
+        "moves" => Some(Box::new(Moves)),
+        _ => None,
+    }
+}
+
+/// Observables recorded when none are selected.
+pub fn defaults<H, S>() -> Vec<Box<dyn Observable<H, S>>>
+where
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    vec![
+        Box::new(Energy),
+        Box::new(Magnetization),
+        Box::new(MagnetizationVector),
+        Box::new(ParallelMagnetization),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{energy::Exchange, state::HeisenbergSpin};
+
+    #[test]
+    fn every_name_is_a_built_in_observable() {
+        let lattice = Lattice::sc(1.0).expand(3, 3, 1);
+        let hamiltonian = Exchange::from_lattice(1.0, &lattice);
+        let mut columns = Vec::new();
+        for name in NAMES {
+            let observable = by_name::<_, HeisenbergSpin>(name, &hamiltonian, &lattice).unwrap();
+            columns.extend(observable.columns());
+        }
+        let mut unique = columns.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), columns.len());
+        assert!(by_name::<_, HeisenbergSpin>("charge", &hamiltonian, &lattice).is_none());
+    }
+}
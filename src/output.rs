@@ -1,49 +1,137 @@
 //! Module for writing simulation data to Parquet files.
 //!
 //! This module provides functionality to write observable data and spin state data
-//! to Parquet files using the Apache Arrow format.
-//! It defines two main structs: `ObservableParquetIO` and `StateParquetIO`,
-//! each responsible for writing different types of data.
+//! to Parquet files using the Apache Arrow format, and to read spin states back
+//! with `StateParquetReader`.
+//! It defines one struct per kind of data, all of them write to a temporary
+//! file that is renamed into place once the writer is dropped.
 
 use crate::{
-    error::IoResult,
-    state::{Spin, State},
+    analysis::StageSummary,
+    error::{IoError, IoResult},
+    state::{Field as SpinField, Spin, State},
     thermostat::Thermostat,
 };
 use arrow::{
-    array::{BooleanArray, Float64Array, UInt64Array},
-    datatypes::{DataType, Field, Schema},
+    array::{
+        ArrayRef, ArrowPrimitiveType, AsArray, BooleanArray, Float64Array, ListBuilder,
+        PrimitiveArray, StringArray, UInt64Array, UInt64Builder,
+    },
+    datatypes::{DataType, Field, Float64Type, Schema, UInt64Type},
     record_batch::RecordBatch,
 };
-use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use parquet::{
+    arrow::{
+        ARROW_SCHEMA_META_KEY, ArrowWriter, ProjectionMask,
+        arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder},
+    },
+    basic::Compression,
+    file::{metadata::KeyValue, properties::WriterProperties},
+};
 use std::{
+    collections::BTreeMap,
     fs::{File, rename},
     iter::repeat_n,
     path::{Path, PathBuf},
     sync::Arc,
 };
+use vegas_lattice::Lattice;
 
-pub struct ObservableParquetOutput {
+/// Key-value metadata stored in the footer of the parquet files.
+///
+/// Simulations record the input, version, seed and sample of the run that
+/// produced a file, see `Input::metadata`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata(BTreeMap<String, String>);
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the value of a key.
+    pub fn set<K: Into<String>, V: ToString>(mut self, key: K, value: V) -> Self {
+        self.0.insert(key.into(), value.to_string());
+        self
+    }
+
+    /// Get the value of a key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// Iterate over the keys and values.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Read the metadata of a parquet file.
+    ///
+    /// The arrow schema stored by the writer is left out, so the metadata
+    /// can be copied to files with other columns.
+    pub fn read<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let file = File::open(path)?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let pairs = builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .into_iter()
+            .flatten()
+            .filter(|kv| kv.key != ARROW_SCHEMA_META_KEY)
+            .filter_map(|kv| kv.value.clone().map(|value| (kv.key.clone(), value)))
+            .collect();
+        Ok(Self(pairs))
+    }
+}
+
+/// Outputs that embed `Metadata` in the files they write.
+pub trait WithMetadata {
+    /// Metadata written along with the data.
+    fn metadata_mut(&mut self) -> &mut Metadata;
+
+    /// Embed the given metadata in the file.
+    fn set_metadata(&mut self, metadata: &Metadata) {
+        *self.metadata_mut() = metadata.clone();
+    }
+}
+
+/// Implement `WithMetadata` for outputs backed by a `ParquetFile`.
+macro_rules! parquet_metadata {
+    ($($output:ty),+) => {
+        $(
+            impl WithMetadata for $output {
+                fn metadata_mut(&mut self) -> &mut Metadata {
+                    &mut self.file.metadata
+                }
+            }
+        )+
+    };
+}
+
+parquet_metadata!(
+    ObservableParquetOutput,
+    StateParquetOutput,
+    StructureFactorParquetOutput,
+    PairCorrelationParquetOutput,
+    GeometryParquetOutput,
+    SummaryParquetOutput
+);
+
+/// A parquet file that is written to a temporary path and renamed on drop.
+struct ParquetFile {
     path: PathBuf,
     temp_path: PathBuf,
     schema: Arc<Schema>,
     writer: Option<ArrowWriter<File>>,
+    metadata: Metadata,
 }
 
-impl ObservableParquetOutput {
-    pub fn try_new<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+impl ParquetFile {
+    fn try_new<P: AsRef<Path>>(path: P, fields: Vec<Field>) -> IoResult<Self> {
         let temp_path = path.as_ref().with_extension("parquet.tmp");
         let file = File::create(&temp_path)?;
-        let schema = Arc::new(Schema::new(vec![
-            Field::new("relax", DataType::Boolean, false),
-            Field::new("stage", DataType::UInt64, false),
-            Field::new("step", DataType::UInt64, false),
-            Field::new("n", DataType::UInt64, false),
-            Field::new("temperature", DataType::Float64, false),
-            Field::new("field", DataType::Float64, false),
-            Field::new("energy", DataType::Float64, false),
-            Field::new("magnetization", DataType::Float64, false),
-        ]));
+        let schema = Arc::new(Schema::new(fields));
         let properties = WriterProperties::builder()
             .set_compression(Compression::SNAPPY)
             .build();
@@ -51,44 +139,14 @@ impl ObservableParquetOutput {
         Ok(Self {
             path: path.as_ref().to_path_buf(),
             temp_path,
-            schema: schema.clone(),
+            schema,
             writer: Some(writer),
+            metadata: Metadata::new(),
         })
     }
 
-    pub fn write<S: Spin>(
-        &mut self,
-        relax: bool,
-        stage: usize,
-        n: usize,
-        thermostat: &Thermostat<S>,
-        energy: &[f64],
-        magnetization: &[f64],
-    ) -> IoResult<()> {
-        debug_assert!(energy.len() == magnetization.len());
-        let relax: BooleanArray = repeat_n(Some(relax), energy.len()).collect();
-        let stage: UInt64Array = repeat_n(stage as u64, energy.len()).collect();
-        let step: UInt64Array = (0..energy.len()).map(|i| i as u64).collect();
-        let n: UInt64Array = repeat_n(n as u64, energy.len()).collect();
-        let temperature: Float64Array = repeat_n(thermostat.temperature(), energy.len()).collect();
-        let field: Float64Array = repeat_n(thermostat.field().magnitude(), energy.len()).collect();
-        let energy: Float64Array = Float64Array::from(energy.to_owned());
-        let magnetization: Float64Array = Float64Array::from(magnetization.to_owned());
-
-        let batch = RecordBatch::try_new(
-            self.schema.clone(),
-            vec![
-                Arc::new(relax),
-                Arc::new(stage),
-                Arc::new(step),
-                Arc::new(n),
-                Arc::new(temperature),
-                Arc::new(field),
-                Arc::new(energy),
-                Arc::new(magnetization),
-            ],
-        )?;
-
+    fn write(&mut self, columns: Vec<ArrayRef>) -> IoResult<()> {
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
         if let Some(writer) = &mut self.writer {
             writer.write(&batch)?;
         } else {
@@ -98,10 +156,13 @@ impl ObservableParquetOutput {
     }
 }
 
-impl Drop for ObservableParquetOutput {
+impl Drop for ParquetFile {
     fn drop(&mut self) {
-        if let Some(self_writer) = self.writer.take() {
-            if let Err(err) = self_writer.close() {
+        if let Some(mut writer) = self.writer.take() {
+            for (key, value) in self.metadata.iter() {
+                writer.append_key_value_metadata(KeyValue::new(key.into(), value.to_string()));
+            }
+            if let Err(err) = writer.close() {
                 eprintln!("error closing parquet writer: {}", err);
                 return;
             }
@@ -112,40 +173,132 @@ impl Drop for ObservableParquetOutput {
     }
 }
 
-pub struct StateParquetOutput {
-    path: PathBuf,
-    temp_path: PathBuf,
-    schema: Arc<Schema>,
-    writer: Option<ArrowWriter<File>>,
+/// Writes observables to a parquet file.
+///
+/// Every row is a step, the first columns describe the stage and the thermal
+/// conditions, followed by one column per observable value. The `field`
+/// column holds the signed field strength along the direction given by
+/// `field_x`, `field_y` and `field_z`. Relaxation stages that met their
+/// equilibration criterion are flagged in `equilibrated` and record the
+/// number of steps it took in `equilibration_steps`, which is null for every
+/// other stage.
+pub struct ObservableParquetOutput {
+    file: ParquetFile,
+    columns: usize,
 }
 
-impl StateParquetOutput {
-    pub fn try_new<P: AsRef<Path>>(path: P) -> IoResult<Self> {
-        let temp_path = path.as_ref().with_extension("parquet.tmp");
-        let file = File::create(&temp_path)?;
-        let schema = Arc::new(Schema::new(vec![
+impl ObservableParquetOutput {
+    pub fn try_new<P: AsRef<Path>>(path: P, columns: &[String]) -> IoResult<Self> {
+        let mut fields = vec![
             Field::new("relax", DataType::Boolean, false),
+            Field::new("equilibrated", DataType::Boolean, false),
+            Field::new("equilibration_steps", DataType::UInt64, true),
             Field::new("stage", DataType::UInt64, false),
             Field::new("step", DataType::UInt64, false),
+            Field::new("n", DataType::UInt64, false),
             Field::new("temperature", DataType::Float64, false),
             Field::new("field", DataType::Float64, false),
-            Field::new("id", DataType::UInt64, false),
-            Field::new("sx", DataType::Float64, false),
-            Field::new("sy", DataType::Float64, false),
-            Field::new("sz", DataType::Float64, false),
-        ]));
-        let properties = WriterProperties::builder()
-            .set_compression(Compression::SNAPPY)
-            .build();
-        let writer = ArrowWriter::try_new(file, schema.clone(), Some(properties))?;
+            Field::new("field_x", DataType::Float64, false),
+            Field::new("field_y", DataType::Float64, false),
+            Field::new("field_z", DataType::Float64, false),
+        ];
+        for name in columns {
+            if fields.iter().any(|field| field.name() == name) {
+                return Err(IoError::DuplicateColumn(name.clone()));
+            }
+            fields.push(Field::new(name, DataType::Float64, false));
+        }
+        let file = ParquetFile::try_new(path, fields)?;
         Ok(Self {
-            path: path.as_ref().to_path_buf(),
-            temp_path,
-            schema: schema.clone(),
-            writer: Some(writer),
+            file,
+            columns: columns.len(),
         })
     }
 
+    /// Write a stage, `values` holds one vector per observable column.
+    ///
+    /// `equilibrated` holds the number of steps a relaxation took to meet
+    /// its equilibration criterion.
+    pub fn write<S: Spin>(
+        &mut self,
+        relax: bool,
+        equilibrated: Option<usize>,
+        stage: usize,
+        n: usize,
+        thermostat: &Thermostat<S>,
+        values: &[Vec<f64>],
+    ) -> IoResult<()> {
+        debug_assert!(values.len() == self.columns);
+        let steps = values.first().map_or(0, |column| column.len());
+        debug_assert!(values.iter().all(|column| column.len() == steps));
+        let orientation = thermostat.field().orientation();
+        let relax: BooleanArray = repeat_n(Some(relax), steps).collect();
+        let equilibration_steps: UInt64Array =
+            repeat_n(equilibrated.map(|steps| steps as u64), steps).collect();
+        let equilibrated: BooleanArray = repeat_n(Some(equilibrated.is_some()), steps).collect();
+        let stage: UInt64Array = repeat_n(stage as u64, steps).collect();
+        let step: UInt64Array = (0..steps).map(|i| i as u64).collect();
+        let n: UInt64Array = repeat_n(n as u64, steps).collect();
+        let temperature: Float64Array = repeat_n(thermostat.temperature(), steps).collect();
+        let field: Float64Array = repeat_n(thermostat.field().value(), steps).collect();
+        let field_x: Float64Array = repeat_n(orientation.sx() / S::length(), steps).collect();
+        let field_y: Float64Array = repeat_n(orientation.sy() / S::length(), steps).collect();
+        let field_z: Float64Array = repeat_n(orientation.sz() / S::length(), steps).collect();
+
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(relax),
+            Arc::new(equilibrated),
+            Arc::new(equilibration_steps),
+            Arc::new(stage),
+            Arc::new(step),
+            Arc::new(n),
+            Arc::new(temperature),
+            Arc::new(field),
+            Arc::new(field_x),
+            Arc::new(field_y),
+            Arc::new(field_z),
+        ];
+        columns.extend(
+            values
+                .iter()
+                .map(|column| Arc::new(Float64Array::from(column.clone())) as ArrayRef),
+        );
+        self.file.write(columns)
+    }
+}
+
+/// Writes spin states to a parquet file.
+///
+/// Every row is the spin of one site, `id`, `sx`, `sy` and `sz`, along with
+/// the stage, step and thermal conditions of its snapshot. The field is
+/// stored as its signed strength in `field` and its unit direction in
+/// `field_x`, `field_y` and `field_z`.
+pub struct StateParquetOutput {
+    file: ParquetFile,
+}
+
+impl StateParquetOutput {
+    pub fn try_new<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let file = ParquetFile::try_new(
+            path,
+            vec![
+                Field::new("relax", DataType::Boolean, false),
+                Field::new("stage", DataType::UInt64, false),
+                Field::new("step", DataType::UInt64, false),
+                Field::new("temperature", DataType::Float64, false),
+                Field::new("field", DataType::Float64, false),
+                Field::new("field_x", DataType::Float64, false),
+                Field::new("field_y", DataType::Float64, false),
+                Field::new("field_z", DataType::Float64, false),
+                Field::new("id", DataType::UInt64, false),
+                Field::new("sx", DataType::Float64, false),
+                Field::new("sy", DataType::Float64, false),
+                Field::new("sz", DataType::Float64, false),
+            ],
+        )?;
+        Ok(Self { file })
+    }
+
     pub fn write<S: Spin>(
         &mut self,
         relax: bool,
@@ -158,44 +311,648 @@ impl StateParquetOutput {
         let stage = UInt64Array::from(repeat_n(stage as u64, state.len()).collect::<Vec<_>>());
         let step = UInt64Array::from(repeat_n(step as u64, state.len()).collect::<Vec<_>>());
         let temperature: Float64Array = repeat_n(thermostat.temperature(), state.len()).collect();
-        let field: Float64Array = repeat_n(thermostat.field().magnitude(), state.len()).collect();
+        let field: Float64Array = repeat_n(thermostat.field().value(), state.len()).collect();
+        let orientation = thermostat.field().orientation();
+        let field_x: Float64Array = repeat_n(orientation.sx() / S::length(), state.len()).collect();
+        let field_y: Float64Array = repeat_n(orientation.sy() / S::length(), state.len()).collect();
+        let field_z: Float64Array = repeat_n(orientation.sz() / S::length(), state.len()).collect();
         let id = UInt64Array::from((0..state.len()).map(|i| i as u64).collect::<Vec<_>>());
         let sx = Float64Array::from(state.spins().iter().map(|s| s.sx()).collect::<Vec<_>>());
         let sy = Float64Array::from(state.spins().iter().map(|s| s.sy()).collect::<Vec<_>>());
         let sz = Float64Array::from(state.spins().iter().map(|s| s.sz()).collect::<Vec<_>>());
-        let batch = RecordBatch::try_new(
-            self.schema.clone(),
+        self.file.write(vec![
+            Arc::new(relax),
+            Arc::new(stage),
+            Arc::new(step),
+            Arc::new(temperature),
+            Arc::new(field),
+            Arc::new(field_x),
+            Arc::new(field_y),
+            Arc::new(field_z),
+            Arc::new(id),
+            Arc::new(sx),
+            Arc::new(sy),
+            Arc::new(sz),
+        ])
+    }
+}
+
+/// Get a primitive column of a record batch by name.
+pub(crate) fn column<'a, T: ArrowPrimitiveType>(
+    batch: &'a RecordBatch,
+    name: &str,
+) -> IoResult<&'a PrimitiveArray<T>> {
+    batch
+        .column_by_name(name)
+        .and_then(|column| column.as_primitive_opt::<T>())
+        .ok_or_else(|| IoError::MissingColumn(name.into()))
+}
+
+/// Snapshot of the state read back from a state parquet file.
+#[derive(Debug, Clone)]
+pub struct Snapshot<S: Spin> {
+    /// Whether the snapshot was taken while relaxing
+    pub relax: bool,
+    /// Stage of the snapshot
+    pub stage: usize,
+    /// Step of the snapshot within its stage
+    pub step: usize,
+    /// Thermostat at the time of the snapshot
+    pub thermostat: Thermostat<S>,
+    /// Spins of the sample
+    pub state: State<S>,
+}
+
+/// Columns of a record batch of a state parquet file.
+///
+/// Columns are resolved once per batch, so reading a row only indexes the
+/// arrays.
+struct StateColumns {
+    rows: usize,
+    relax: BooleanArray,
+    stage: UInt64Array,
+    step: UInt64Array,
+    temperature: Float64Array,
+    field: Float64Array,
+    direction: Option<[Float64Array; 3]>,
+    id: UInt64Array,
+    sx: Float64Array,
+    sy: Float64Array,
+    sz: Float64Array,
+}
+
+impl StateColumns {
+    fn try_new(batch: &RecordBatch) -> IoResult<Self> {
+        let relax = batch
+            .column_by_name("relax")
+            .and_then(|column| column.as_boolean_opt())
+            .ok_or_else(|| IoError::MissingColumn("relax".into()))?
+            .clone();
+        let direction = match (
+            column::<Float64Type>(batch, "field_x"),
+            column::<Float64Type>(batch, "field_y"),
+            column::<Float64Type>(batch, "field_z"),
+        ) {
+            (Ok(x), Ok(y), Ok(z)) => Some([x.clone(), y.clone(), z.clone()]),
+            _ => None,
+        };
+        Ok(Self {
+            rows: batch.num_rows(),
+            relax,
+            stage: column::<UInt64Type>(batch, "stage")?.clone(),
+            step: column::<UInt64Type>(batch, "step")?.clone(),
+            temperature: column::<Float64Type>(batch, "temperature")?.clone(),
+            field: column::<Float64Type>(batch, "field")?.clone(),
+            direction,
+            id: column::<UInt64Type>(batch, "id")?.clone(),
+            sx: column::<Float64Type>(batch, "sx")?.clone(),
+            sy: column::<Float64Type>(batch, "sy")?.clone(),
+            sz: column::<Float64Type>(batch, "sz")?.clone(),
+        })
+    }
+
+    fn key(&self, row: usize) -> (usize, usize) {
+        (
+            self.stage.value(row) as usize,
+            self.step.value(row) as usize,
+        )
+    }
+
+    fn projections(&self, row: usize) -> [f64; 3] {
+        [self.sx.value(row), self.sy.value(row), self.sz.value(row)]
+    }
+}
+
+/// Find the stage and step of the last snapshot matching the given ones.
+///
+/// Only the `stage` and `step` columns of the file are read.
+fn find_snapshot<P: AsRef<Path>>(
+    path: P,
+    stage: Option<usize>,
+    step: Option<usize>,
+) -> IoResult<Option<(usize, usize)>> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let mask = ProjectionMask::columns(builder.parquet_schema(), ["stage", "step"]);
+    let mut found = None;
+    for batch in builder.with_projection(mask).build()? {
+        let batch = batch?;
+        let stages = column::<UInt64Type>(&batch, "stage")?;
+        let steps = column::<UInt64Type>(&batch, "step")?;
+        for (&row_stage, &row_step) in stages.values().iter().zip(steps.values()) {
+            let key = (row_stage as usize, row_step as usize);
+            if stage.is_none_or(|s| s == key.0) && step.is_none_or(|s| s == key.1) {
+                found = Some(key);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Read the spin projections of a snapshot written by `StateParquetOutput`.
+///
+/// The snapshot is chosen like `StateParquetReader::find` does, but the
+/// projections are returned as written, ordered by site, instead of being
+/// rebuilt as spins of a given type.
+pub fn read_state_projections<P: AsRef<Path>>(
+    path: P,
+    stage: Option<usize>,
+    step: Option<usize>,
+) -> IoResult<Vec<[f64; 3]>> {
+    let not_found = || IoError::SnapshotNotFound(path.as_ref().display().to_string());
+    let key = find_snapshot(&path, stage, step)?.ok_or_else(not_found)?;
+    let batches = ParquetRecordBatchReaderBuilder::try_new(File::open(&path)?)?.build()?;
+    let mut projections = Vec::new();
+    for batch in batches {
+        let columns = StateColumns::try_new(&batch?)?;
+        for row in (0..columns.rows).filter(|&row| columns.key(row) == key) {
+            let id = columns.id.value(row) as usize;
+            if projections.len() <= id {
+                projections.resize(id + 1, [0.0; 3]);
+            }
+            projections[id] = columns.projections(row);
+        }
+    }
+    Ok(projections)
+}
+
+/// Reads back the snapshots written by `StateParquetOutput`.
+///
+/// Snapshots are read one record batch at a time and yielded in the order
+/// they were written, spins are rebuilt with `Spin::closest`. Files written
+/// before the field direction was recorded get a field along `Spin::up`.
+pub struct StateParquetReader<S: Spin> {
+    path: PathBuf,
+    batches: ParquetRecordBatchReader,
+    columns: Option<StateColumns>,
+    row: usize,
+    only: Option<(usize, usize)>,
+    current: Option<(Snapshot<S>, Vec<S>)>,
+}
+
+impl<S: Spin> StateParquetReader<S> {
+    pub fn try_new<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let file = File::open(&path)?;
+        let batches = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            batches,
+            columns: None,
+            row: 0,
+            only: None,
+            current: None,
+        })
+    }
+
+    /// Find the last snapshot matching the given stage and step.
+    ///
+    /// Either of them matches any snapshot when omitted, so leaving both
+    /// out gives the last snapshot of the file. Only the spins of the
+    /// matching snapshot are decoded.
+    pub fn find(
+        mut self,
+        stage: Option<usize>,
+        step: Option<usize>,
+    ) -> IoResult<Option<Snapshot<S>>> {
+        let Some(key) = find_snapshot(&self.path, stage, step)? else {
+            return Ok(None);
+        };
+        self.only = Some(key);
+        self.next().transpose()
+    }
+
+    /// Add the spin at the current row, returns the finished snapshot when
+    /// the row starts a new one.
+    fn read_row(&mut self, columns: &StateColumns) -> Option<Snapshot<S>> {
+        let row = self.row;
+        let (stage, step) = columns.key(row);
+        if let Some((snapshot, _)) = &self.current
+            && (snapshot.stage, snapshot.step) != (stage, step)
+        {
+            return self.finish();
+        }
+        self.row += 1;
+        if self.only.is_some_and(|only| only != (stage, step)) {
+            return None;
+        }
+        if self.current.is_none() {
+            let direction = match &columns.direction {
+                Some([x, y, z]) => [x.value(row), y.value(row), z.value(row)],
+                None => [S::up().sx(), S::up().sy(), S::up().sz()],
+            };
+            let field = SpinField::along(direction, columns.field.value(row));
+            let snapshot = Snapshot {
+                relax: columns.relax.value(row),
+                stage,
+                step,
+                thermostat: Thermostat::new(columns.temperature.value(row), field),
+                state: State::up_with_size(0),
+            };
+            self.current = Some((snapshot, Vec::new()));
+        }
+        let id = columns.id.value(row) as usize;
+        let [sx, sy, sz] = columns.projections(row);
+        if let Some((_, spins)) = &mut self.current {
+            if spins.len() <= id {
+                spins.resize(id + 1, S::up());
+            }
+            spins[id] = S::closest(sx, sy, sz);
+        }
+        None
+    }
+
+    fn finish(&mut self) -> Option<Snapshot<S>> {
+        self.current.take().map(|(mut snapshot, spins)| {
+            snapshot.state = spins.into_iter().collect();
+            snapshot
+        })
+    }
+}
+
+impl<S: Spin> Iterator for StateParquetReader<S> {
+    type Item = IoResult<Snapshot<S>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let columns = match self.columns.take() {
+                Some(columns) if self.row < columns.rows => columns,
+                _ => match self.batches.next() {
+                    Some(Ok(batch)) => {
+                        self.row = 0;
+                        match StateColumns::try_new(&batch) {
+                            Ok(columns) => columns,
+                            Err(error) => return Some(Err(error)),
+                        }
+                    }
+                    Some(Err(error)) => return Some(Err(error.into())),
+                    None => return self.finish().map(Ok),
+                },
+            };
+            let snapshot = self.read_row(&columns);
+            self.columns = Some(columns);
+            if let Some(snapshot) = snapshot {
+                return Some(Ok(snapshot));
+            }
+        }
+    }
+}
+
+/// Writes the static structure factor of every measurement stage.
+///
+/// Wave vectors `qx`, `qy` and `qz` are given in units of `2π / a`.
+pub struct StructureFactorParquetOutput {
+    file: ParquetFile,
+}
+
+impl StructureFactorParquetOutput {
+    pub fn try_new<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let file = ParquetFile::try_new(
+            path,
             vec![
-                Arc::new(relax),
-                Arc::new(stage),
-                Arc::new(step),
-                Arc::new(temperature),
-                Arc::new(field),
-                Arc::new(id),
-                Arc::new(sx),
-                Arc::new(sy),
-                Arc::new(sz),
+                Field::new("stage", DataType::UInt64, false),
+                Field::new("temperature", DataType::Float64, false),
+                Field::new("field", DataType::Float64, false),
+                Field::new("qx", DataType::Float64, false),
+                Field::new("qy", DataType::Float64, false),
+                Field::new("qz", DataType::Float64, false),
+                Field::new("structure_factor", DataType::Float64, false),
             ],
         )?;
-        if let Some(writer) = &mut self.writer {
-            writer.write(&batch)?;
-        } else {
-            return Err(std::io::Error::other("Writer has been closed"))?;
+        Ok(Self { file })
+    }
+
+    pub fn write<S: Spin>(
+        &mut self,
+        stage: usize,
+        thermostat: &Thermostat<S>,
+        q: &[[f64; 3]],
+        structure_factor: &[f64],
+    ) -> IoResult<()> {
+        debug_assert!(q.len() == structure_factor.len());
+        let stage: UInt64Array = repeat_n(stage as u64, q.len()).collect();
+        let temperature: Float64Array = repeat_n(thermostat.temperature(), q.len()).collect();
+        let field: Float64Array = repeat_n(thermostat.field().value(), q.len()).collect();
+        let qx: Float64Array = q.iter().map(|q| q[0]).collect();
+        let qy: Float64Array = q.iter().map(|q| q[1]).collect();
+        let qz: Float64Array = q.iter().map(|q| q[2]).collect();
+        let structure_factor = Float64Array::from(structure_factor.to_owned());
+        self.file.write(vec![
+            Arc::new(stage),
+            Arc::new(temperature),
+            Arc::new(field),
+            Arc::new(qx),
+            Arc::new(qy),
+            Arc::new(qz),
+            Arc::new(structure_factor),
+        ])
+    }
+}
+
+/// Writes the spin-spin correlation of every measurement stage.
+///
+/// Each row is a shell of sites at the same `distance`, with the number of
+/// `pairs` in the shell, the averaged `correlation` and its `connected` part.
+pub struct PairCorrelationParquetOutput {
+    file: ParquetFile,
+}
+
+impl PairCorrelationParquetOutput {
+    pub fn try_new<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let file = ParquetFile::try_new(
+            path,
+            vec![
+                Field::new("stage", DataType::UInt64, false),
+                Field::new("temperature", DataType::Float64, false),
+                Field::new("field", DataType::Float64, false),
+                Field::new("distance", DataType::Float64, false),
+                Field::new("pairs", DataType::UInt64, false),
+                Field::new("correlation", DataType::Float64, false),
+                Field::new("connected", DataType::Float64, false),
+            ],
+        )?;
+        Ok(Self { file })
+    }
+
+    pub fn write<S: Spin>(
+        &mut self,
+        stage: usize,
+        thermostat: &Thermostat<S>,
+        distance: &[f64],
+        pairs: &[usize],
+        correlation: &[f64],
+        connected: &[f64],
+    ) -> IoResult<()> {
+        let len = distance.len();
+        debug_assert!(pairs.len() == len && correlation.len() == len && connected.len() == len);
+        let stage: UInt64Array = repeat_n(stage as u64, len).collect();
+        let temperature: Float64Array = repeat_n(thermostat.temperature(), len).collect();
+        let field: Float64Array = repeat_n(thermostat.field().value(), len).collect();
+        let distance = Float64Array::from(distance.to_owned());
+        let pairs: UInt64Array = pairs.iter().map(|&p| p as u64).collect();
+        let correlation = Float64Array::from(correlation.to_owned());
+        let connected = Float64Array::from(connected.to_owned());
+        self.file.write(vec![
+            Arc::new(stage),
+            Arc::new(temperature),
+            Arc::new(field),
+            Arc::new(distance),
+            Arc::new(pairs),
+            Arc::new(correlation),
+            Arc::new(connected),
+        ])
+    }
+}
+
+/// Writes the geometry of the sample, one row per site.
+///
+/// Rows hold the site `id` used by the state files, its position, its
+/// `kind` and the ids of its neighbors, so snapshots can be rendered
+/// without rebuilding the lattice.
+pub struct GeometryParquetOutput {
+    file: ParquetFile,
+}
+
+impl GeometryParquetOutput {
+    pub fn try_new<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let neighbor = Arc::new(Field::new("item", DataType::UInt64, true));
+        let file = ParquetFile::try_new(
+            path,
+            vec![
+                Field::new("id", DataType::UInt64, false),
+                Field::new("x", DataType::Float64, false),
+                Field::new("y", DataType::Float64, false),
+                Field::new("z", DataType::Float64, false),
+                Field::new("kind", DataType::Utf8, false),
+                Field::new("neighbors", DataType::List(neighbor), false),
+            ],
+        )?;
+        Ok(Self { file })
+    }
+
+    pub fn write(&mut self, lattice: &Lattice) -> IoResult<()> {
+        let sites = lattice.sites();
+        let mut neighbors = vec![Vec::new(); sites.len()];
+        for edge in lattice.edges() {
+            neighbors[edge.source()].push(edge.target() as u64);
+            neighbors[edge.target()].push(edge.source() as u64);
         }
-        Ok(())
+        let id: UInt64Array = (0..sites.len() as u64).collect();
+        let x: Float64Array = sites.iter().map(|site| site.position().0).collect();
+        let y: Float64Array = sites.iter().map(|site| site.position().1).collect();
+        let z: Float64Array = sites.iter().map(|site| site.position().2).collect();
+        let kind: StringArray = sites.iter().map(|site| Some(site.kind())).collect();
+        let mut list = ListBuilder::new(UInt64Builder::new());
+        for around in neighbors {
+            list.values().append_slice(&around);
+            list.append(true);
+        }
+        self.file.write(vec![
+            Arc::new(id),
+            Arc::new(x),
+            Arc::new(y),
+            Arc::new(z),
+            Arc::new(kind),
+            Arc::new(list.finish()),
+        ])
     }
 }
 
-impl Drop for StateParquetOutput {
-    fn drop(&mut self) {
-        if let Some(writer) = self.writer.take() {
-            if let Err(err) = writer.close() {
-                eprintln!("error closing parquet writer: {}", err);
-                return;
+/// Writes the summaries of the measurement stages of a simulation.
+pub struct SummaryParquetOutput {
+    file: ParquetFile,
+}
+
+impl SummaryParquetOutput {
+    pub fn try_new<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let fields = StageSummary::COLUMNS
+            .iter()
+            .map(|&name| match name {
+                "stage" | "n" | "steps" => Field::new(name, DataType::UInt64, false),
+                _ => Field::new(name, DataType::Float64, false),
+            })
+            .collect();
+        let file = ParquetFile::try_new(path, fields)?;
+        Ok(Self { file })
+    }
+
+    pub fn write(&mut self, summaries: &[StageSummary]) -> IoResult<()> {
+        let columns = StageSummary::COLUMNS
+            .iter()
+            .enumerate()
+            .map(|(k, &name)| -> ArrayRef {
+                let values = summaries.iter().map(|summary| summary.values()[k]);
+                match name {
+                    "stage" | "n" | "steps" => {
+                        Arc::new(values.map(|v| v as u64).collect::<UInt64Array>())
+                    }
+                    _ => Arc::new(values.collect::<Float64Array>()),
+                }
+            })
+            .collect();
+        self.file.write(columns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{HeisenbergSpin, IsingSpin};
+    use rand::SeedableRng;
+    use rand_pcg::Pcg64;
+
+    #[test]
+    fn state_snapshots_round_trip() {
+        let mut rng = Pcg64::seed_from_u64(3);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.parquet");
+        // Large enough to span several record batches per snapshot.
+        let states: Vec<_> = (0..3)
+            .map(|_| State::<HeisenbergSpin>::rand_with_size(&mut rng, 2500))
+            .collect();
+        let thermostat = Thermostat::new(1.5, SpinField::along([1.0, 0.0, 0.0], 0.5));
+        {
+            let mut output = StateParquetOutput::try_new(&path).unwrap();
+            for (step, state) in states.iter().enumerate() {
+                output
+                    .write(step == 0, 1, step, &thermostat, state)
+                    .unwrap();
             }
-            if let Err(err) = rename(&self.temp_path, &self.path) {
-                eprintln!("error renaming parquet file: {}", err);
+        }
+        let snapshots: Vec<_> = StateParquetReader::<HeisenbergSpin>::try_new(&path)
+            .unwrap()
+            .collect::<IoResult<_>>()
+            .unwrap();
+        assert_eq!(snapshots.len(), 3);
+        for (step, (snapshot, state)) in snapshots.iter().zip(&states).enumerate() {
+            assert_eq!(
+                (snapshot.relax, snapshot.stage, snapshot.step),
+                (step == 0, 1, step)
+            );
+            assert_eq!(snapshot.thermostat.temperature(), 1.5);
+            assert!((snapshot.thermostat.field().sx() - 0.5).abs() < 1e-12);
+            for (read, written) in snapshot.state.spins().iter().zip(state.spins()) {
+                assert!((read.dot(written) - 1.0).abs() < 1e-12);
             }
-        };
+        }
+        let ising = StateParquetReader::<IsingSpin>::try_new(&path)
+            .unwrap()
+            .find(Some(1), Some(1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(ising.state.len(), 2500);
+        assert_eq!(ising.step, 1);
+        let projections = read_state_projections(&path, None, Some(1)).unwrap();
+        for (read, written) in projections.iter().zip(states[1].spins()) {
+            assert_eq!(*read, [written.sx(), written.sy(), written.sz()]);
+        }
+        assert!(matches!(
+            read_state_projections(&path, Some(2), None),
+            Err(IoError::SnapshotNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn geometry_lists_the_neighbors_of_every_site() {
+        let lattice = Lattice::sc(1.0).expand(3, 3, 1).drop_z();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("geometry.parquet");
+        {
+            let mut output = GeometryParquetOutput::try_new(&path).unwrap();
+            output.write(&lattice).unwrap();
+        }
+        let file = File::open(&path).unwrap();
+        let batches: Vec<_> = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 9);
+        let neighbors = batch.column_by_name("neighbors").unwrap().as_list::<i32>();
+        assert!((0..9).all(|row| neighbors.value(row).len() == 4));
+        let x = column::<Float64Type>(batch, "x").unwrap();
+        assert_eq!(x.value(1), lattice.sites()[1].position().0);
+    }
+
+    #[test]
+    fn observables_schema_lists_the_requested_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("observables.parquet");
+        let columns = vec!["energy".to_string(), "magnetization".to_string()];
+        {
+            let mut output = ObservableParquetOutput::try_new(&path, &columns).unwrap();
+            let thermostat = Thermostat::<HeisenbergSpin>::near_zero();
+            let values = vec![vec![-1.0, -2.0], vec![0.5, 0.25]];
+            output
+                .write(true, Some(2), 0, 1, &thermostat, &values)
+                .unwrap();
+            output
+                .write(false, None, 1, 1, &thermostat, &values)
+                .unwrap();
+        }
+        let file = File::open(&path).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        let schema = builder.schema().clone();
+        let names: Vec<_> = schema.fields().iter().map(|field| field.name()).collect();
+        assert_eq!(
+            names,
+            [
+                "relax",
+                "equilibrated",
+                "equilibration_steps",
+                "stage",
+                "step",
+                "n",
+                "temperature",
+                "field",
+                "field_x",
+                "field_y",
+                "field_z",
+                "energy",
+                "magnetization",
+            ]
+        );
+        assert!(
+            schema
+                .field_with_name("equilibration_steps")
+                .unwrap()
+                .is_nullable()
+        );
+        let batches: Vec<_> = builder.build().unwrap().collect::<Result<_, _>>().unwrap();
+        let steps: Vec<_> = batches
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column_by_name("equilibration_steps")
+                    .unwrap()
+                    .as_primitive::<UInt64Type>()
+                    .iter()
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(steps, [Some(2), Some(2), None, None]);
+        let energy: Vec<_> = batches
+            .iter()
+            .flat_map(|batch| {
+                column::<Float64Type>(batch, "energy")
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(energy, [-1.0, -2.0, -1.0, -2.0]);
+    }
+
+    #[test]
+    fn observables_reject_duplicate_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("observables.parquet");
+        let columns = vec!["energy".to_string(), "energy".to_string()];
+        assert!(matches!(
+            ObservableParquetOutput::try_new(&path, &columns),
+            Err(IoError::DuplicateColumn(name)) if name == "energy"
+        ));
+        let columns = vec!["temperature".to_string()];
+        assert!(ObservableParquetOutput::try_new(&path, &columns).is_err());
     }
 }
@@ -10,6 +10,8 @@
 //! * `Relax` - A program that relaxes the system at a specified temperature for a given number of steps.
 //! * `CoolDown` - A program that gradually cools down the system from a maximum temperature to a minimum temperature over a series of steps.
 //! * `HysteresisLoop` - A program that simulates a hysteresis loop by varying the external magnetic field and measuring the system's response.
+//! * `Forc` - A program that measures first-order reversal curves along a given field direction.
+//! * `Schedule` - A program that follows explicit temperature and field points, a CSV file or a ramp.
 //!
 //! # Example
 //!
@@ -43,13 +45,19 @@ use core::f64;
 
 use crate::{
     energy::Hamiltonian,
-    error::{ProgramError, ProgramResult},
+    error::{IoError, ProgramError, ProgramResult},
     integrator::Integrator,
-    machine::Machine,
+    machine::{Equilibration, Machine},
     state::{Field, Spin},
 };
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    iter::once,
+    path::{Path, PathBuf},
+};
 
 /// A program is a sequence of steps that can be run on a system.
 pub trait Program {
@@ -67,12 +75,18 @@ pub trait Program {
 pub struct Relax {
     steps: usize,
     temperature: f64,
+    #[serde(default)]
+    equilibration: Option<Equilibration>,
 }
 
 impl Relax {
     /// Create a new relaxation program.
     pub fn new(steps: usize, temperature: f64) -> Self {
-        Self { steps, temperature }
+        Self {
+            steps,
+            temperature,
+            equilibration: None,
+        }
     }
 
     /// Set the number of steps.
@@ -86,6 +100,12 @@ impl Relax {
         self.temperature = temperature;
         self
     }
+
+    /// Stop relaxing early once the given criterion is met.
+    pub fn set_equilibration(mut self, equilibration: Equilibration) -> Self {
+        self.equilibration = Some(equilibration);
+        self
+    }
 }
 
 impl Default for Relax {
@@ -109,12 +129,85 @@ impl Program for Relax {
             return Err(ProgramError::ZeroTemperature);
         }
         machine.set_thermostat(machine.thermostat().with_temperature(self.temperature));
-        machine.relax_for(rng, self.steps)?;
+        machine.relax_with(rng, self.steps, self.equilibration.as_ref())?;
         Ok(())
     }
 }
 
+/// Adaptive refinement of the cooling step.
+///
+/// After every temperature the specific heat and susceptibility are compared
+/// with the previous temperature. When either changes by more than
+/// `tolerance` (relative) the program steps back and samples the midpoint of
+/// the interval, bisecting each half again as needed up to `refinements`
+/// times, before cooling on from the lowest temperature.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Adaptive {
+    #[serde(default = "Adaptive::default_tolerance")]
+    tolerance: f64,
+    #[serde(default = "Adaptive::default_refinements")]
+    refinements: u32,
+}
+
+impl Adaptive {
+    /// Create a new adaptive refinement.
+    pub fn new(tolerance: f64, refinements: u32) -> Self {
+        Self {
+            tolerance,
+            refinements,
+        }
+    }
+
+    fn default_tolerance() -> f64 {
+        0.1
+    }
+
+    fn default_refinements() -> u32 {
+        4
+    }
+
+    /// Whether the responses changed too much between two temperatures.
+    fn exceeded(&self, previous: &[f64; 2], current: &[f64; 2]) -> bool {
+        let relative = |a: f64, b: f64| (b - a).abs() / a.abs().max(b.abs()).max(f64::EPSILON);
+        previous
+            .iter()
+            .zip(current)
+            .any(|(a, b)| relative(*a, *b) > self.tolerance)
+    }
+
+    /// Bisect the interval from `high` to `low` while the responses at its
+    /// ends differ by more than the tolerance.
+    fn refine<F>(
+        &self,
+        spacing: Spacing,
+        high: (f64, [f64; 2]),
+        low: (f64, [f64; 2]),
+        depth: u32,
+        sample: &mut F,
+    ) -> ProgramResult<()>
+    where
+        F: FnMut(f64) -> ProgramResult<[f64; 2]>,
+    {
+        if depth >= self.refinements || !self.exceeded(&high.1, &low.1) {
+            return Ok(());
+        }
+        let temperature = spacing.temperatures(high.0, low.0, 3)[1];
+        let middle = (temperature, sample(temperature)?);
+        self.refine(spacing, high, middle, depth + 1, sample)?;
+        self.refine(spacing, middle, low, depth + 1, sample)
+    }
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Self::new(Self::default_tolerance(), Self::default_refinements())
+    }
+}
+
 /// A program that cools the system to find the Curie temperature.
+///
+/// With linear spacing the temperature decreases by `cool_rate` at every
+/// point, with geometric spacing it is multiplied by `1 - cool_rate`.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CoolDown {
     max_temperature: f64,
@@ -122,6 +215,12 @@ pub struct CoolDown {
     cool_rate: f64,
     relax: usize,
     steps: usize,
+    #[serde(default)]
+    spacing: Spacing,
+    #[serde(default)]
+    adaptive: Option<Adaptive>,
+    #[serde(default)]
+    equilibration: Option<Equilibration>,
 }
 
 impl CoolDown {
@@ -139,6 +238,9 @@ impl CoolDown {
             cool_rate,
             relax,
             steps,
+            spacing: Spacing::default(),
+            adaptive: None,
+            equilibration: None,
         }
     }
 
@@ -171,6 +273,51 @@ impl CoolDown {
         self.steps = steps;
         self
     }
+
+    /// Set the temperature spacing.
+    pub fn set_spacing(mut self, spacing: Spacing) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Refine the cooling step adaptively.
+    pub fn set_adaptive(mut self, adaptive: Adaptive) -> Self {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    /// Stop relaxing early once the given criterion is met.
+    pub fn set_equilibration(mut self, equilibration: Equilibration) -> Self {
+        self.equilibration = Some(equilibration);
+        self
+    }
+
+    /// Walk the temperatures of the program, `sample` relaxes and measures
+    /// at a temperature and returns the specific heat and susceptibility.
+    fn cool<F>(&self, mut sample: F) -> ProgramResult<()>
+    where
+        F: FnMut(f64) -> ProgramResult<[f64; 2]>,
+    {
+        let mut temperature = self.max_temperature;
+        let mut previous: Option<(f64, [f64; 2])> = None;
+        loop {
+            let current = (temperature, sample(temperature)?);
+            if let Some(adaptive) = &self.adaptive
+                && let Some(previous) = previous
+            {
+                adaptive.refine(self.spacing, previous, current, 0, &mut sample)?;
+            }
+            previous = Some(current);
+            temperature = match self.spacing {
+                Spacing::Linear => temperature - self.cool_rate,
+                Spacing::Geometric => temperature * (1.0 - self.cool_rate),
+            };
+            if temperature < self.min_temperature {
+                break;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Default for CoolDown {
@@ -199,21 +346,86 @@ impl Program for CoolDown {
         if self.cool_rate < f64::EPSILON {
             return Err(ProgramError::ZeroCoolRate);
         }
-        let mut temperature = self.max_temperature;
-        loop {
+        if self.spacing == Spacing::Geometric && self.cool_rate >= 1.0 {
+            return Err(ProgramError::CoolRateTooLarge);
+        }
+        self.cool(|temperature| {
             machine.set_thermostat(machine.thermostat().with_temperature(temperature));
-            machine.relax_for(rng, self.relax)?;
-            machine.measure_for(rng, self.steps)?;
-            temperature -= self.cool_rate;
-            if temperature < self.min_temperature {
-                break;
+            machine.relax_with(rng, self.relax, self.equilibration.as_ref())?;
+            if self.adaptive.is_some() {
+                let summary = machine.measure_and_summarize_for(rng, self.steps)?;
+                Ok([summary.specific_heat(), summary.susceptibility()])
+            } else {
+                machine.measure_for(rng, self.steps)?;
+                Ok([f64::NAN; 2])
             }
+        })
+    }
+}
+
+/// Default direction for field sweeps.
+fn default_direction() -> [f64; 3] {
+    [0.0, 0.0, 1.0]
+}
+
+/// Check that a field along `direction` reaches the spins.
+///
+/// Planar spins ignore the z axis and Ising spins every axis but z, so a
+/// direction can be non-zero and still give no field at all.
+fn check_direction<S: Spin>(direction: &[f64; 3]) -> ProgramResult<()> {
+    if Field::<S>::along(*direction, 1.0).magnitude() < f64::EPSILON {
+        return Err(ProgramError::ZeroDirection);
+    }
+    Ok(())
+}
+
+/// Field values from `start` to `end` (inclusive) separated by `step`.
+///
+/// The last point is always `end`, even when the distance is not a multiple
+/// of `step`.
+fn field_points(start: f64, end: f64, step: f64) -> impl Iterator<Item = f64> {
+    let count = ((end - start).abs() / step - 1e-9).ceil().max(0.0) as usize;
+    let step = if end < start { -step } else { step };
+    (0..count)
+        .map(move |i| start + i as f64 * step)
+        .chain(once(end))
+}
+
+/// Sweep the field along a direction, relaxing and optionally measuring at
+/// every point.
+fn sweep_field<R, I, H, S>(
+    rng: &mut R,
+    machine: &mut Machine<H, I, S>,
+    direction: [f64; 3],
+    fields: impl Iterator<Item = f64>,
+    relax: usize,
+    steps: usize,
+    equilibration: Option<&Equilibration>,
+) -> ProgramResult<()>
+where
+    R: Rng,
+    I: Integrator<S>,
+    H: Hamiltonian<S>,
+    S: Spin,
+{
+    for value in fields {
+        let field = Field::along(direction, value);
+        machine.set_thermostat(machine.thermostat().with_field(field));
+        machine.relax_with(rng, relax, equilibration)?;
+        if steps > 0 {
+            machine.measure_for(rng, steps)?;
         }
-        Ok(())
     }
+    Ok(())
 }
 
 /// A program that runs a hysteresis loop.
+///
+/// The field is swept along `direction` from zero up to `max_field`, down to
+/// `min_field` and back up to `max_field`. The minimum field defaults to
+/// `-max_field`, a larger value traces a minor loop.
+///
+/// The direction defaults to z, so planar spins need one in the xy plane.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct HysteresisLoop {
     steps: usize,
@@ -221,6 +433,12 @@ pub struct HysteresisLoop {
     temperature: f64,
     max_field: f64,
     field_step: f64,
+    #[serde(default)]
+    min_field: Option<f64>,
+    #[serde(default = "default_direction")]
+    direction: [f64; 3],
+    #[serde(default)]
+    equilibration: Option<Equilibration>,
 }
 
 impl HysteresisLoop {
@@ -238,6 +456,9 @@ impl HysteresisLoop {
             temperature,
             max_field,
             field_step,
+            min_field: None,
+            direction: default_direction(),
+            equilibration: None,
         }
     }
 
@@ -265,11 +486,29 @@ impl HysteresisLoop {
         self
     }
 
+    /// Set the minimum field, for minor loops.
+    pub fn set_min_field(mut self, min_field: f64) -> Self {
+        self.min_field = Some(min_field);
+        self
+    }
+
     /// Set the field step.
     pub fn set_field_step(mut self, field_step: f64) -> Self {
         self.field_step = field_step;
         self
     }
+
+    /// Set the direction of the field.
+    pub fn set_direction(mut self, direction: [f64; 3]) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Stop relaxing early once the given criterion is met.
+    pub fn set_equilibration(mut self, equilibration: Equilibration) -> Self {
+        self.equilibration = Some(equilibration);
+        self
+    }
 }
 
 impl Default for HysteresisLoop {
@@ -286,6 +525,7 @@ impl Program for HysteresisLoop {
         H: Hamiltonian<S>,
         S: Spin,
     {
+        let min_field = self.min_field.unwrap_or(-self.max_field);
         if self.steps == 0 {
             return Err(ProgramError::NoSteps);
         }
@@ -298,39 +538,736 @@ impl Program for HysteresisLoop {
         if self.field_step < f64::EPSILON {
             return Err(ProgramError::ZeroFieldStep);
         }
+        if self.max_field <= min_field {
+            return Err(ProgramError::FieldMaxLessThanMin);
+        }
+        check_direction::<S>(&self.direction)?;
         machine.set_thermostat(machine.thermostat().with_temperature(self.temperature));
-        let mut magnitude = 0.0;
-        loop {
-            let field = Field::new(S::up(), magnitude);
-            machine.set_thermostat(machine.thermostat().with_field(field));
-            machine.relax_for(rng, self.relax)?;
-            machine.measure_for(rng, self.steps)?;
-            magnitude += self.field_step;
-            if magnitude > self.max_field {
-                break;
-            }
+        let branches = field_points(0.0, self.max_field, self.field_step)
+            .chain(field_points(self.max_field, min_field, self.field_step).skip(1))
+            .chain(field_points(min_field, self.max_field, self.field_step).skip(1));
+        sweep_field(
+            rng,
+            machine,
+            self.direction,
+            branches,
+            self.relax,
+            self.steps,
+            self.equilibration.as_ref(),
+        )
+    }
+}
+
+/// A program that measures first-order reversal curves (FORC).
+///
+/// For every reversal field the sample is saturated at `max_field`, the field
+/// is lowered to the reversal field relaxing at each point, and then the
+/// sample is measured while the field goes back up to `max_field`. Reversal
+/// fields go from `max_field - reversal_step` down to `min_reversal`, which
+/// defaults to `-max_field`.
+///
+/// The direction defaults to z, so planar spins need one in the xy plane.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Forc {
+    steps: usize,
+    relax: usize,
+    temperature: f64,
+    max_field: f64,
+    field_step: f64,
+    reversal_step: f64,
+    #[serde(default)]
+    min_reversal: Option<f64>,
+    #[serde(default = "default_direction")]
+    direction: [f64; 3],
+    #[serde(default)]
+    equilibration: Option<Equilibration>,
+}
+
+impl Forc {
+    /// Create a new first-order reversal curve program.
+    pub fn new(
+        steps: usize,
+        relax: usize,
+        temperature: f64,
+        max_field: f64,
+        field_step: f64,
+        reversal_step: f64,
+    ) -> Self {
+        Self {
+            steps,
+            relax,
+            temperature,
+            max_field,
+            field_step,
+            reversal_step,
+            min_reversal: None,
+            direction: default_direction(),
+            equilibration: None,
         }
-        loop {
-            let field = Field::new(S::up(), magnitude);
-            machine.set_thermostat(machine.thermostat().with_field(field));
-            machine.relax_for(rng, self.relax)?;
-            machine.measure_for(rng, self.steps)?;
-            magnitude -= self.field_step;
-            if magnitude < -self.max_field {
-                break;
-            }
+    }
+
+    /// Set the number of steps.
+    pub fn set_steps(mut self, steps: usize) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    /// Set the number of relaxation steps.
+    pub fn set_relax(mut self, relax: usize) -> Self {
+        self.relax = relax;
+        self
+    }
+
+    /// Set the temperature.
+    pub fn set_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Set the maximum field.
+    pub fn set_max_field(mut self, max_field: f64) -> Self {
+        self.max_field = max_field;
+        self
+    }
+
+    /// Set the field step along each curve.
+    pub fn set_field_step(mut self, field_step: f64) -> Self {
+        self.field_step = field_step;
+        self
+    }
+
+    /// Set the distance between reversal fields.
+    pub fn set_reversal_step(mut self, reversal_step: f64) -> Self {
+        self.reversal_step = reversal_step;
+        self
+    }
+
+    /// Set the lowest reversal field.
+    pub fn set_min_reversal(mut self, min_reversal: f64) -> Self {
+        self.min_reversal = Some(min_reversal);
+        self
+    }
+
+    /// Set the direction of the field.
+    pub fn set_direction(mut self, direction: [f64; 3]) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Stop relaxing early once the given criterion is met.
+    pub fn set_equilibration(mut self, equilibration: Equilibration) -> Self {
+        self.equilibration = Some(equilibration);
+        self
+    }
+}
+
+impl Default for Forc {
+    fn default() -> Self {
+        Self::new(1000, 1000, 3.0, 1.0, 0.1, 0.1)
+    }
+}
+
+impl Program for Forc {
+    fn run<R, I, H, S>(&self, rng: &mut R, machine: &mut Machine<H, I, S>) -> ProgramResult<()>
+    where
+        R: Rng,
+        I: Integrator<S>,
+        H: Hamiltonian<S>,
+        S: Spin,
+    {
+        let min_reversal = self.min_reversal.unwrap_or(-self.max_field);
+        if self.steps == 0 {
+            return Err(ProgramError::NoSteps);
         }
-        loop {
-            let field = Field::new(S::up(), magnitude);
-            machine.set_thermostat(machine.thermostat().with_field(field));
-            machine.relax_for(rng, self.relax)?;
-            machine.measure_for(rng, self.steps)?;
-            magnitude += self.field_step;
-            if magnitude > self.max_field {
-                break;
+        if self.temperature < f64::EPSILON {
+            return Err(ProgramError::ZeroTemperature);
+        }
+        if self.max_field < f64::EPSILON {
+            return Err(ProgramError::ZeroField);
+        }
+        if self.field_step < f64::EPSILON || self.reversal_step < f64::EPSILON {
+            return Err(ProgramError::ZeroFieldStep);
+        }
+        if self.max_field <= min_reversal {
+            return Err(ProgramError::FieldMaxLessThanMin);
+        }
+        check_direction::<S>(&self.direction)?;
+        machine.set_thermostat(machine.thermostat().with_temperature(self.temperature));
+        let reversals = field_points(self.max_field, min_reversal, self.reversal_step).skip(1);
+        for reversal in reversals {
+            let descent = field_points(self.max_field, reversal, self.field_step);
+            sweep_field(
+                rng,
+                machine,
+                self.direction,
+                descent,
+                self.relax,
+                0,
+                self.equilibration.as_ref(),
+            )?;
+            let curve = field_points(reversal, self.max_field, self.field_step);
+            sweep_field(
+                rng,
+                machine,
+                self.direction,
+                curve,
+                self.relax,
+                self.steps,
+                self.equilibration.as_ref(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Spacing between consecutive temperatures of a ramp.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Spacing {
+    /// Constant difference between temperatures
+    #[default]
+    Linear,
+    /// Constant ratio between temperatures
+    Geometric,
+}
+
+impl Spacing {
+    /// Temperatures from `from` to `to` (inclusive) using `points` points.
+    pub fn temperatures(&self, from: f64, to: f64, points: usize) -> Vec<f64> {
+        if points < 2 {
+            return vec![from; points];
+        }
+        let last = (points - 1) as f64;
+        (0..points)
+            .map(|i| match self {
+                Spacing::Linear => from + (to - from) * i as f64 / last,
+                Spacing::Geometric => from * (to / from).powf(i as f64 / last),
+            })
+            .collect()
+    }
+}
+
+/// How the field is applied during a temperature ramp.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Protocol {
+    /// The field is on while cooling and while measuring
+    #[default]
+    #[serde(rename = "fc")]
+    FieldCooled,
+    /// The sample is cooled in zero field and measured with the field on
+    #[serde(rename = "zfc")]
+    ZeroFieldCooled,
+}
+
+/// A single point of a schedule.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SchedulePoint {
+    temperature: f64,
+    #[serde(default)]
+    field: [f64; 3],
+    relax: usize,
+    steps: usize,
+}
+
+impl SchedulePoint {
+    /// Create a new schedule point, no measurement is done if `steps` is zero.
+    pub fn new(temperature: f64, field: [f64; 3], relax: usize, steps: usize) -> Self {
+        Self {
+            temperature,
+            field,
+            relax,
+            steps,
+        }
+    }
+
+    /// Read schedule points from a CSV source.
+    ///
+    /// The first line is a header naming the columns, `temperature`, `relax`
+    /// and `steps` are required while `field_x`, `field_y` and `field_z`
+    /// default to zero. Empty lines and lines starting with `#` are skipped.
+    pub fn read_csv<B: BufRead>(reader: B) -> ProgramResult<Vec<Self>> {
+        let mut lines = reader
+            .lines()
+            .enumerate()
+            .map(|(index, line)| line.map(|l| (index + 1, l)).map_err(IoError::from))
+            .filter(|line| {
+                line.as_ref()
+                    .map(|(_, l)| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+                    .unwrap_or(true)
+            });
+        let header: Vec<String> = match lines.next() {
+            Some(line) => line?.1.split(',').map(|c| c.trim().to_string()).collect(),
+            None => return Ok(Vec::new()),
+        };
+        let column = |name: &str| header.iter().position(|c| c == name);
+        let required = |name: &str| {
+            column(name)
+                .ok_or_else(|| ProgramError::InvalidSchedule(format!("missing column `{}`", name)))
+        };
+        let temperature = required("temperature")?;
+        let relax = required("relax")?;
+        let steps = required("steps")?;
+        let field = [column("field_x"), column("field_y"), column("field_z")];
+        let mut points = Vec::new();
+        for line in lines {
+            let (number, line) = line?;
+            let values: Vec<&str> = line.split(',').map(|v| v.trim()).collect();
+            let invalid =
+                || ProgramError::InvalidSchedule(format!("invalid row on line {}", number));
+            let value = |index: usize| values.get(index).ok_or_else(invalid);
+            let mut point_field = [0.0; 3];
+            for (component, index) in point_field.iter_mut().zip(field) {
+                if let Some(index) = index {
+                    *component = value(index)?.parse().map_err(|_| invalid())?;
+                }
             }
+            points.push(SchedulePoint::new(
+                value(temperature)?.parse().map_err(|_| invalid())?,
+                point_field,
+                value(relax)?.parse().map_err(|_| invalid())?,
+                value(steps)?.parse().map_err(|_| invalid())?,
+            ));
+        }
+        Ok(points)
+    }
+}
+
+/// A temperature ramp expanded into schedule points.
+///
+/// A ramp that heats the sample (`from < to`) first cools it from `to` down
+/// to `from` relaxing at each point, with the field on for field-cooled
+/// protocols and off for zero-field-cooled ones. Measurements are always
+/// taken with the field on.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Ramp {
+    from: f64,
+    to: f64,
+    points: usize,
+    relax: usize,
+    steps: usize,
+    #[serde(default)]
+    spacing: Spacing,
+    #[serde(default)]
+    field: [f64; 3],
+    #[serde(default)]
+    protocol: Protocol,
+}
+
+impl Ramp {
+    /// Create a new linear, zero field ramp.
+    pub fn new(from: f64, to: f64, points: usize, relax: usize, steps: usize) -> Self {
+        Self {
+            from,
+            to,
+            points,
+            relax,
+            steps,
+            spacing: Spacing::default(),
+            field: [0.0; 3],
+            protocol: Protocol::default(),
+        }
+    }
+
+    /// Set the temperature spacing.
+    pub fn set_spacing(mut self, spacing: Spacing) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Set the measuring field.
+    pub fn set_field(mut self, field: [f64; 3]) -> Self {
+        self.field = field;
+        self
+    }
+
+    /// Set the field protocol.
+    pub fn set_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Expand the ramp into schedule points.
+    pub fn schedule_points(&self) -> ProgramResult<Vec<SchedulePoint>> {
+        if self.points == 0 {
+            return Err(ProgramError::NoSteps);
+        }
+        if self.from < f64::EPSILON || self.to < f64::EPSILON {
+            return Err(ProgramError::ZeroTemperature);
+        }
+        let heating = self.from < self.to;
+        if !heating && self.protocol == Protocol::ZeroFieldCooled {
+            return Err(ProgramError::InvalidSchedule(
+                "zero-field-cooled ramps must heat the sample".into(),
+            ));
+        }
+        let temperatures = self.spacing.temperatures(self.from, self.to, self.points);
+        let mut points = Vec::new();
+        if heating {
+            let field = match self.protocol {
+                Protocol::FieldCooled => self.field,
+                Protocol::ZeroFieldCooled => [0.0; 3],
+            };
+            points.extend(
+                temperatures
+                    .iter()
+                    .rev()
+                    .map(|&t| SchedulePoint::new(t, field, self.relax, 0)),
+            );
         }
+        points.extend(
+            temperatures
+                .iter()
+                .map(|&t| SchedulePoint::new(t, self.field, self.relax, self.steps)),
+        );
+        Ok(points)
+    }
+}
+
+/// A program that follows an arbitrary temperature and field schedule.
+///
+/// Points are taken from the explicit `points` list, then from the CSV file
+/// at `path` and finally from the `ramp`, in that order.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Schedule {
+    #[serde(default)]
+    points: Vec<SchedulePoint>,
+    #[serde(default)]
+    path: Option<PathBuf>,
+    #[serde(default)]
+    ramp: Option<Ramp>,
+    #[serde(default)]
+    equilibration: Option<Equilibration>,
+}
+
+impl Schedule {
+    /// Create an empty schedule.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a point to the schedule.
+    pub fn add_point(mut self, point: SchedulePoint) -> Self {
+        self.points.push(point);
+        self
+    }
+
+    /// Set the CSV file to read points from.
+    pub fn set_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the temperature ramp.
+    pub fn set_ramp(mut self, ramp: Ramp) -> Self {
+        self.ramp = Some(ramp);
+        self
+    }
 
+    /// Stop relaxing early once the given criterion is met.
+    pub fn set_equilibration(mut self, equilibration: Equilibration) -> Self {
+        self.equilibration = Some(equilibration);
+        self
+    }
+
+    /// Collect all the points of the schedule.
+    pub fn schedule_points(&self) -> ProgramResult<Vec<SchedulePoint>> {
+        let mut points = self.points.clone();
+        if let Some(path) = &self.path {
+            let file = File::open(path).map_err(IoError::from)?;
+            points.extend(SchedulePoint::read_csv(BufReader::new(file))?);
+        }
+        if let Some(ramp) = &self.ramp {
+            points.extend(ramp.schedule_points()?);
+        }
+        Ok(points)
+    }
+}
+
+impl Program for Schedule {
+    fn run<R, I, H, S>(&self, rng: &mut R, machine: &mut Machine<H, I, S>) -> ProgramResult<()>
+    where
+        R: Rng,
+        I: Integrator<S>,
+        H: Hamiltonian<S>,
+        S: Spin,
+    {
+        let points = self.schedule_points()?;
+        if points.is_empty() {
+            return Err(ProgramError::NoSteps);
+        }
+        if points.iter().any(|p| p.temperature < f64::EPSILON) {
+            return Err(ProgramError::ZeroTemperature);
+        }
+        for point in points {
+            let [x, y, z] = point.field;
+            let field = Field::along(point.field, (x * x + y * y + z * z).sqrt());
+            machine.set_thermostat(
+                machine
+                    .thermostat()
+                    .with_temperature(point.temperature)
+                    .with_field(field),
+            );
+            machine.relax_with(rng, point.relax, self.equilibration.as_ref())?;
+            if point.steps > 0 {
+                machine.measure_for(rng, point.steps)?;
+            }
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        energy::{Hamiltonian, Zeeman},
+        error::{InstrumentResult, ProgramError},
+        instrument::Instrument,
+        integrator::{MetropolisFlipIntegrator, MetropolisIntegrator},
+        machine::Machine,
+        program::{
+            Adaptive, CoolDown, Forc, HysteresisLoop, Program, Protocol, Ramp, Schedule,
+            SchedulePoint, Spacing, field_points,
+        },
+        state::{Field, IsingSpin, Spin, State, XYSpin},
+        thermostat::Thermostat,
+    };
+    use rand::SeedableRng;
+    use rand_pcg::Pcg64;
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Records the z component of the field of every relaxation and
+    /// measurement.
+    #[derive(Clone, Default)]
+    struct FieldLog(Rc<RefCell<Vec<(bool, f64)>>>);
+
+    impl<H: Hamiltonian<S>, S: Spin> Instrument<H, S> for FieldLog {
+        fn on_relax_start(
+            &mut self,
+            thermostat: &Thermostat<S>,
+            _hamiltonian: &H,
+            _state: &State<S>,
+        ) -> InstrumentResult<()> {
+            self.0.borrow_mut().push((true, thermostat.field().sz()));
+            Ok(())
+        }
+
+        fn on_measure_start(
+            &mut self,
+            thermostat: &Thermostat<S>,
+            _hamiltonian: &H,
+            _state: &State<S>,
+        ) -> InstrumentResult<()> {
+            self.0.borrow_mut().push((false, thermostat.field().sz()));
+            Ok(())
+        }
+    }
+
+    /// Run a program on a few Ising spins and return the fields it used.
+    fn field_log<P: Program>(program: &P) -> Vec<(bool, f64)> {
+        let log = FieldLog::default();
+        let mut machine = Machine::new(
+            Thermostat::new(1.0, Field::zero()),
+            Zeeman::new(),
+            MetropolisFlipIntegrator::new(),
+            vec![Box::new(log.clone())],
+            State::<IsingSpin>::up_with_size(4),
+        );
+        program
+            .run(&mut Pcg64::seed_from_u64(1), &mut machine)
+            .unwrap();
+        log.0.take()
+    }
+
+    fn assert_fields(log: &[(bool, f64)], relax: bool, expected: &[f64]) {
+        let fields: Vec<f64> = log
+            .iter()
+            .filter(|(r, _)| *r == relax)
+            .map(|(_, f)| *f)
+            .collect();
+        assert_eq!(fields.len(), expected.len(), "{:?}", fields);
+        for (field, expected) in fields.iter().zip(expected) {
+            assert!((field - expected).abs() < 1e-12, "{:?}", fields);
+        }
+    }
+
+    #[test]
+    fn hysteresis_visits_both_turning_points() {
+        let program = HysteresisLoop::new(1, 1, 1.0, 1.0, 0.3)
+            .set_min_field(-0.5)
+            .set_direction([1.0, 0.0, 1.0]);
+        let log = field_log(&program);
+        let expected: Vec<f64> = [
+            0.0, 0.3, 0.6, 0.9, 1.0, 0.7, 0.4, 0.1, -0.2, -0.5, -0.2, 0.1, 0.4, 0.7, 1.0,
+        ]
+        .iter()
+        .map(|f| f / 2f64.sqrt())
+        .collect();
+        assert_fields(&log, true, &expected);
+        assert_fields(&log, false, &expected);
+    }
+
+    #[test]
+    fn field_sweeps_reject_directions_the_spins_ignore() {
+        let program = HysteresisLoop::new(1, 1, 1.0, 1.0, 0.5);
+        let mut machine = Machine::new(
+            Thermostat::new(1.0, Field::zero()),
+            Zeeman::new(),
+            MetropolisIntegrator::new(),
+            vec![],
+            State::<XYSpin>::up_with_size(4),
+        );
+        assert!(matches!(
+            program.run(&mut Pcg64::seed_from_u64(1), &mut machine),
+            Err(ProgramError::ZeroDirection)
+        ));
+        let program = Forc::new(1, 1, 1.0, 1.0, 0.5, 0.5).set_direction([1.0, 0.0, 0.0]);
+        let mut machine = Machine::new(
+            Thermostat::new(1.0, Field::zero()),
+            Zeeman::new(),
+            MetropolisFlipIntegrator::new(),
+            vec![],
+            State::<IsingSpin>::up_with_size(4),
+        );
+        assert!(matches!(
+            program.run(&mut Pcg64::seed_from_u64(1), &mut machine),
+            Err(ProgramError::ZeroDirection)
+        ));
+    }
+
+    #[test]
+    fn forc_measures_every_reversal_curve() {
+        let log = field_log(&Forc::new(1, 1, 1.0, 1.0, 0.5, 0.5));
+        let curves = [
+            vec![0.5, 1.0],
+            vec![0.0, 0.5, 1.0],
+            vec![-0.5, 0.0, 0.5, 1.0],
+            vec![-1.0, -0.5, 0.0, 0.5, 1.0],
+        ];
+        assert_fields(&log, false, &curves.concat());
+        let descents = [
+            vec![1.0, 0.5],
+            vec![1.0, 0.5, 0.0],
+            vec![1.0, 0.5, 0.0, -0.5],
+            vec![1.0, 0.5, 0.0, -0.5, -1.0],
+        ];
+        let relaxed: Vec<f64> = descents
+            .iter()
+            .zip(&curves)
+            .flat_map(|(descent, curve)| descent.iter().chain(curve))
+            .copied()
+            .collect();
+        assert_fields(&log, true, &relaxed);
+    }
+
+    #[test]
+    fn field_points_include_both_ends() {
+        let points: Vec<_> = field_points(-1.0, 1.0, 0.5).collect();
+        assert_eq!(points, vec![-1.0, -0.5, 0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn field_points_go_down() {
+        let points: Vec<_> = field_points(1.0, 0.0, 0.4).collect();
+        assert_eq!(points.len(), 4);
+        assert!((points[2] - 0.2).abs() < 1e-12);
+        assert_eq!(points[3], 0.0);
+    }
+
+    #[test]
+    fn field_points_end_exactly() {
+        let points: Vec<_> = field_points(0.0, 1.0, 0.3).collect();
+        assert_eq!(points.len(), 5);
+        assert_eq!(points[4], 1.0);
+        assert_eq!(field_points(0.5, 0.5, 0.1).collect::<Vec<_>>(), vec![0.5]);
+    }
+
+    #[test]
+    fn geometric_spacing_keeps_ratio() {
+        let temperatures = Spacing::Geometric.temperatures(4.0, 1.0, 3);
+        assert!((temperatures[1] - 2.0).abs() < 1e-12);
+        assert!((temperatures[2] - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn zero_field_cooled_ramp_cools_without_field() {
+        let points = Ramp::new(1.0, 2.0, 2, 10, 100)
+            .set_field([0.0, 0.0, 0.5])
+            .set_protocol(Protocol::ZeroFieldCooled)
+            .schedule_points()
+            .unwrap();
+        assert_eq!(
+            points,
+            vec![
+                SchedulePoint::new(2.0, [0.0; 3], 10, 0),
+                SchedulePoint::new(1.0, [0.0; 3], 10, 0),
+                SchedulePoint::new(1.0, [0.0, 0.0, 0.5], 10, 100),
+                SchedulePoint::new(2.0, [0.0, 0.0, 0.5], 10, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_field_cooled_ramp_must_heat() {
+        let ramp = Ramp::new(2.0, 1.0, 2, 10, 100).set_protocol(Protocol::ZeroFieldCooled);
+        assert!(ramp.schedule_points().is_err());
+    }
+
+    #[test]
+    fn schedule_points_read_from_csv() {
+        let csv = "# a comment\ntemperature, relax, steps, field_z\n2.0, 10, 100, 0.5\n\n1.0, 10, 0, -0.5\n";
+        let points = SchedulePoint::read_csv(csv.as_bytes()).unwrap();
+        assert_eq!(
+            points,
+            vec![
+                SchedulePoint::new(2.0, [0.0, 0.0, 0.5], 10, 100),
+                SchedulePoint::new(1.0, [0.0, 0.0, -0.5], 10, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn schedule_csv_requires_columns() {
+        let csv = "temperature, steps\n2.0, 100\n";
+        assert!(SchedulePoint::read_csv(csv.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn schedule_fields_are_projected_onto_the_spins() {
+        let program = Schedule::new()
+            .add_point(SchedulePoint::new(1.0, [0.5, 0.0, 0.0], 1, 1))
+            .add_point(SchedulePoint::new(1.0, [0.0, 0.0, -0.5], 1, 1));
+        assert_fields(&field_log(&program), false, &[0.0, -0.5]);
+    }
+
+    #[test]
+    fn invalid_csv_rows_report_their_line() {
+        let csv = "# a comment\ntemperature, relax, steps\n\n2.0, 10, 100\n1.0, ten, 0\n";
+        let error = SchedulePoint::read_csv(csv.as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("line 5"), "{}", error);
+    }
+
+    #[test]
+    fn adaptive_cool_down_refines_around_a_peak() {
+        let peak = |t: f64| 1.0 / (0.01 + (t - 2.0).powi(2));
+        let sampled = |program: CoolDown| {
+            let mut temperatures = Vec::new();
+            program
+                .cool(|t| {
+                    temperatures.push(t);
+                    Ok([peak(t), 1.0])
+                })
+                .unwrap();
+            temperatures
+        };
+        let coarse = sampled(CoolDown::new(3.0, 1.0, 0.5, 0, 1));
+        assert_eq!(coarse, vec![3.0, 2.5, 2.0, 1.5, 1.0]);
+        let fine = sampled(CoolDown::new(3.0, 1.0, 0.5, 0, 1).set_adaptive(Adaptive::new(0.5, 3)));
+        let near = |ts: &[f64]| ts.iter().filter(|t| (*t - 2.0).abs() < 0.3).count();
+        assert_eq!(near(&coarse), 1);
+        assert!(near(&fine) >= 5, "{:?}", fine);
+        // Refined points fill the interval the coarse step skipped.
+        assert!(fine.contains(&2.25) && fine.contains(&1.75));
+        assert_eq!(fine.iter().filter(|t| **t == 2.0).count(), 1);
+        assert!(fine.iter().all(|t| (1.0..=3.0).contains(t)));
+    }
+}
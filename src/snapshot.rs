@@ -0,0 +1,231 @@
+//! Text snapshots of the spins for visualization tools.
+//!
+//! Snapshots pair the site positions with the spin vectors, so they can be
+//! rendered as arrows in ParaView or OVITO. Extended XYZ files hold every
+//! frame one after the other, legacy VTK files hold a single frame each and
+//! are numbered so they open as a series.
+
+use crate::{
+    error::IoResult,
+    geometry::Geometry,
+    state::{Spin, State},
+    thermostat::Thermostat,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use vegas_lattice::Lattice;
+
+/// File format of the snapshots.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    /// Extended XYZ, every frame in the same file
+    #[default]
+    Xyz,
+    /// Legacy VTK polydata, one file per frame
+    Vtk,
+}
+
+/// Conditions of the sample when a snapshot was taken.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub relax: bool,
+    pub stage: usize,
+    pub step: usize,
+}
+
+/// Writes snapshots of the spins with the positions of the sites.
+pub struct SnapshotWriter {
+    path: PathBuf,
+    format: SnapshotFormat,
+    geometry: Geometry,
+    kinds: Vec<String>,
+    xyz: Option<BufWriter<File>>,
+    frames: usize,
+}
+
+impl SnapshotWriter {
+    /// Create a writer for the sites of the given lattice.
+    ///
+    /// XYZ snapshots are written to `path`, VTK snapshots to files named
+    /// after `path` with the frame number appended to the file stem.
+    pub fn try_new<P: AsRef<Path>>(
+        path: P,
+        format: SnapshotFormat,
+        lattice: &Lattice,
+    ) -> IoResult<Self> {
+        let xyz = match format {
+            SnapshotFormat::Xyz => Some(BufWriter::new(File::create(&path)?)),
+            SnapshotFormat::Vtk => None,
+        };
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            format,
+            geometry: Geometry::from_lattice(lattice),
+            kinds: lattice
+                .sites()
+                .iter()
+                .map(|site| site.kind().to_string())
+                .collect(),
+            xyz,
+            frames: 0,
+        })
+    }
+
+    /// Write a snapshot of the state.
+    pub fn write<S: Spin>(
+        &mut self,
+        frame: Frame,
+        thermostat: &Thermostat<S>,
+        state: &State<S>,
+    ) -> IoResult<()> {
+        debug_assert!(state.len() == self.geometry.len());
+        match self.format {
+            SnapshotFormat::Xyz => self.write_xyz(frame, thermostat, state)?,
+            SnapshotFormat::Vtk => self.write_vtk(frame, thermostat, state)?,
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Path of the VTK file of the given frame.
+    pub fn frame_path(&self, frame: usize) -> PathBuf {
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.path
+            .with_file_name(format!("{}_{:06}.vtk", stem, frame))
+    }
+
+    fn write_xyz<S: Spin>(
+        &mut self,
+        frame: Frame,
+        thermostat: &Thermostat<S>,
+        state: &State<S>,
+    ) -> IoResult<()> {
+        let Some(out) = &mut self.xyz else {
+            return Ok(());
+        };
+        let [lx, ly, lz] = self.geometry.size();
+        let pbc = self.geometry.periodic().map(|p| if p { "T" } else { "F" });
+        writeln!(out, "{}", state.len())?;
+        writeln!(
+            out,
+            "Lattice=\"{} 0 0 0 {} 0 0 0 {}\" Properties=species:S:1:pos:R:3:spin:R:3 \
+             pbc=\"{} {} {}\" Time={} stage={} step={} relax={} temperature={} field={}",
+            lx,
+            ly,
+            lz,
+            pbc[0],
+            pbc[1],
+            pbc[2],
+            self.frames,
+            frame.stage,
+            frame.step,
+            if frame.relax { "T" } else { "F" },
+            thermostat.temperature(),
+            thermostat.field().value(),
+        )?;
+        for ((kind, [x, y, z]), spin) in self
+            .kinds
+            .iter()
+            .zip(self.geometry.positions())
+            .zip(state.spins())
+        {
+            writeln!(
+                out,
+                "{} {} {} {} {} {} {}",
+                kind,
+                x,
+                y,
+                z,
+                spin.sx(),
+                spin.sy(),
+                spin.sz()
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn write_vtk<S: Spin>(
+        &mut self,
+        frame: Frame,
+        thermostat: &Thermostat<S>,
+        state: &State<S>,
+    ) -> IoResult<()> {
+        let n = state.len();
+        let mut out = BufWriter::new(File::create(self.frame_path(self.frames))?);
+        writeln!(out, "# vtk DataFile Version 3.0")?;
+        writeln!(
+            out,
+            "vegas stage {} step {} relax {} temperature {} field {}",
+            frame.stage,
+            frame.step,
+            frame.relax,
+            thermostat.temperature(),
+            thermostat.field().value()
+        )?;
+        writeln!(out, "ASCII")?;
+        writeln!(out, "DATASET POLYDATA")?;
+        writeln!(out, "POINTS {} double", n)?;
+        for [x, y, z] in self.geometry.positions() {
+            writeln!(out, "{} {} {}", x, y, z)?;
+        }
+        writeln!(out, "VERTICES {} {}", n, 2 * n)?;
+        for i in 0..n {
+            writeln!(out, "1 {}", i)?;
+        }
+        writeln!(out, "POINT_DATA {}", n)?;
+        writeln!(out, "VECTORS spin double")?;
+        for spin in state.spins() {
+            writeln!(out, "{} {} {}", spin.sx(), spin.sy(), spin.sz())?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::HeisenbergSpin;
+
+    #[test]
+    fn snapshots_hold_one_line_per_site() {
+        let lattice = Lattice::sc(1.0).expand(2, 2, 1).drop_z();
+        let state = State::<HeisenbergSpin>::up_with_size(4);
+        let thermostat = Thermostat::near_zero();
+        let frame = Frame {
+            relax: false,
+            stage: 1,
+            step: 10,
+        };
+        let dir = tempfile::tempdir().unwrap();
+
+        let path = dir.path().join("snapshot.xyz");
+        let mut xyz = SnapshotWriter::try_new(&path, SnapshotFormat::Xyz, &lattice).unwrap();
+        xyz.write(frame, &thermostat, &state).unwrap();
+        xyz.write(frame, &thermostat, &state).unwrap();
+        drop(xyz);
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 2 * 6);
+        assert!(text.lines().nth(1).unwrap().contains("pbc=\"T T F\""));
+        assert!(text.lines().nth(2).unwrap().ends_with(" 0 0 1"));
+
+        let path = dir.path().join("snapshot.vtk");
+        let mut vtk = SnapshotWriter::try_new(&path, SnapshotFormat::Vtk, &lattice).unwrap();
+        vtk.write(frame, &thermostat, &state).unwrap();
+        let frame_path = vtk.frame_path(0);
+        assert!(frame_path.ends_with("snapshot_000000.vtk"));
+        let text = std::fs::read_to_string(&frame_path).unwrap();
+        assert!(text.contains("POINTS 4 double"));
+        assert!(text.contains("VECTORS spin double"));
+    }
+}
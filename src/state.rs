@@ -4,6 +4,11 @@
 //! of an atom in a magnetic material. The library provides a `Spin` trait that
 //! you can implement for your own spin types.
 //!
+//! Besides Ising and Heisenberg spins, the module provides planar `XYSpin`,
+//! and the `Q` state `ClockSpin` and `PottsSpin` types. Spins longer than one
+//! are available as `IsingSpinS` and `HeisenbergSpinS`, which report their
+//! length through `Spin::length`.
+//!
 //! # Examples
 //!
 //! ```rust
@@ -23,6 +28,7 @@ use rand::{
     Rng,
     distr::{Distribution, Uniform},
 };
+use std::f64::consts::PI;
 use std::iter::Sum;
 
 /// This trait specifies what a spin is.
@@ -38,8 +44,25 @@ pub trait Spin: Clone {
     fn rand<R: Rng>(rng: &mut R) -> Self;
 
     /// Create a spin from its projections along the x, y, and z axes.
+    ///
+    /// The orientation of the field is a spin of full length pointing along
+    /// the projections, and its magnitude is the length of the projections.
     fn from_projections(sx: f64, sy: f64, sz: f64) -> Field<Self>;
 
+    /// Spin closest to the given projections.
+    ///
+    /// Reads back the projections of a spin written to a file, so spins of
+    /// every length should round trip through their projections.
+    fn closest(sx: f64, sy: f64, sz: f64) -> Self {
+        Self::from_projections(sx, sy, sz).orientation().clone()
+    }
+
+    /// Length of the spins of this type, the largest projection a spin can
+    /// have along any axis.
+    fn length() -> f64 {
+        1.0
+    }
+
     /// Flip the spin.
     fn flip(&self) -> Self;
 
@@ -191,7 +214,390 @@ impl Spin for HeisenbergSpin {
     }
 }
 
+/// Ising spin of length `S = TWO_S / 2`.
+///
+/// The spin takes one of the `2S + 1` projections `-S, -S + 1, ..., S` along
+/// the z axis, so `IsingSpinS<1>` is the usual spin one half and
+/// `IsingSpinS<2>` is the spin one of the Blume-Capel model. Projections are
+/// stored doubled so half integer spins are exact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsingSpinS<const TWO_S: usize>(i32);
+
+/// Spin one Ising spin of the Blume-Capel model.
+pub type BlumeCapelSpin = IsingSpinS<2>;
+
+impl<const TWO_S: usize> IsingSpinS<TWO_S> {
+    /// Create a spin with the given doubled projection, which must be one
+    /// of `-2S, -2S + 2, ..., 2S`.
+    pub fn new(twice_sz: i32) -> Self {
+        debug_assert!(twice_sz.unsigned_abs() as usize <= TWO_S);
+        debug_assert!((twice_sz + TWO_S as i32) % 2 == 0);
+        IsingSpinS(twice_sz)
+    }
+}
+
+impl<const TWO_S: usize> Spin for IsingSpinS<TWO_S> {
+    fn up() -> Self {
+        IsingSpinS(TWO_S as i32)
+    }
+
+    fn down() -> Self {
+        IsingSpinS(-(TWO_S as i32))
+    }
+
+    fn rand<R: Rng>(rng: &mut R) -> Self {
+        let range =
+            Uniform::new_inclusive(0, TWO_S as i32).expect("should always be able to create");
+        IsingSpinS(2 * range.sample(rng) - TWO_S as i32)
+    }
+
+    fn from_projections(_sx: f64, _sy: f64, sz: f64) -> Field<Self> {
+        if sz >= 0.0 {
+            Field::new(Self::up(), sz)
+        } else {
+            Field::new(Self::down(), -sz)
+        }
+    }
+
+    fn closest(_sx: f64, _sy: f64, sz: f64) -> Self {
+        let level = ((2.0 * sz + TWO_S as f64) / 2.0)
+            .round()
+            .clamp(0.0, TWO_S as f64) as i32;
+        IsingSpinS(2 * level - TWO_S as i32)
+    }
+
+    fn length() -> f64 {
+        TWO_S as f64 / 2.0
+    }
+
+    #[inline]
+    fn dot(&self, other: &Self) -> f64 {
+        self.sz() * other.sz()
+    }
+
+    #[inline]
+    fn sx(&self) -> f64 {
+        0.0
+    }
+
+    #[inline]
+    fn sy(&self) -> f64 {
+        0.0
+    }
+
+    #[inline]
+    fn sz(&self) -> f64 {
+        self.0 as f64 / 2.0
+    }
+
+    fn flip(&self) -> Self {
+        IsingSpinS(-self.0)
+    }
+}
+
+/// Classical Heisenberg spin of length `S = TWO_S / 2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeisenbergSpinS<const TWO_S: usize>([f64; 3]);
+
+impl<const TWO_S: usize> Spin for HeisenbergSpinS<TWO_S> {
+    fn up() -> Self {
+        HeisenbergSpinS([0f64, 0f64, Self::length()])
+    }
+
+    fn down() -> Self {
+        HeisenbergSpinS([0f64, 0f64, -Self::length()])
+    }
+
+    fn rand<R: Rng>(rng: &mut R) -> Self {
+        let (x, y, z) = marsaglia(rng);
+        let length = Self::length();
+        HeisenbergSpinS([x * length, y * length, z * length])
+    }
+
+    fn from_projections(sx: f64, sy: f64, sz: f64) -> Field<Self> {
+        let magnitude = (sx * sx + sy * sy + sz * sz).sqrt();
+        if magnitude < f64::EPSILON {
+            Field::zero()
+        } else {
+            let scale = Self::length() / magnitude;
+            Field::new(
+                HeisenbergSpinS([sx * scale, sy * scale, sz * scale]),
+                magnitude,
+            )
+        }
+    }
+
+    fn length() -> f64 {
+        TWO_S as f64 / 2.0
+    }
+
+    #[inline]
+    fn dot(&self, other: &Self) -> f64 {
+        self.0[0] * other.0[0] + self.0[1] * other.0[1] + self.0[2] * other.0[2]
+    }
+
+    #[inline]
+    fn sx(&self) -> f64 {
+        self.0[0]
+    }
+
+    #[inline]
+    fn sy(&self) -> f64 {
+        self.0[1]
+    }
+
+    #[inline]
+    fn sz(&self) -> f64 {
+        self.0[2]
+    }
+
+    fn flip(&self) -> Self {
+        HeisenbergSpinS([-self.0[0], -self.0[1], -self.0[2]])
+    }
+}
+
+/// Planar spin, a unit vector in the xy plane.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XYSpin([f64; 2]);
+
+impl XYSpin {
+    /// Create a planar spin pointing at the given angle from the x axis.
+    pub fn from_angle(angle: f64) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        XYSpin([cos, sin])
+    }
+}
+
+impl Spin for XYSpin {
+    fn up() -> Self {
+        XYSpin([1f64, 0f64])
+    }
+
+    fn down() -> Self {
+        XYSpin([-1f64, 0f64])
+    }
+
+    fn rand<R: Rng>(rng: &mut R) -> Self {
+        let range = Uniform::new(0f64, 2f64 * PI).expect("should always be able to create");
+        XYSpin::from_angle(range.sample(rng))
+    }
+
+    /// The z projection is ignored, planar spins can't point out of plane.
+    fn from_projections(sx: f64, sy: f64, _sz: f64) -> Field<Self> {
+        let magnitude = sx.hypot(sy);
+        if magnitude < f64::EPSILON {
+            Field::zero()
+        } else {
+            Field::new(XYSpin([sx / magnitude, sy / magnitude]), magnitude)
+        }
+    }
+
+    #[inline]
+    fn dot(&self, other: &Self) -> f64 {
+        self.0[0] * other.0[0] + self.0[1] * other.0[1]
+    }
+
+    #[inline]
+    fn sx(&self) -> f64 {
+        self.0[0]
+    }
+
+    #[inline]
+    fn sy(&self) -> f64 {
+        self.0[1]
+    }
+
+    #[inline]
+    fn sz(&self) -> f64 {
+        0.0
+    }
+
+    fn flip(&self) -> Self {
+        XYSpin([-self.0[0], -self.0[1]])
+    }
+}
+
+/// State paired with `k` by the flip of a `Q` state spin.
+///
+/// Even `Q` pairs opposite states. Odd `Q` has no opposite states, so `k` is
+/// mirrored across the line that takes state `0` to state `Q / 2`, which
+/// leaves one state in place. Either way flipping twice gives `k` back.
+fn flipped_state<const Q: usize>(k: usize) -> usize {
+    if Q.is_multiple_of(2) {
+        (k + Q / 2) % Q
+    } else {
+        (Q + Q / 2 - k) % Q
+    }
+}
+
+/// Index of the state of a `Q` state spin closest to the given angle.
+fn nearest_state<const Q: usize>(angle: f64) -> usize {
+    let step = 2f64 * PI / Q as f64;
+    ((angle / step).round() as i64).rem_euclid(Q as i64) as usize
+}
+
+/// Clock spin, a planar spin restricted to `Q` evenly spaced angles.
+///
+/// State `k` points at the angle `2πk/Q`, the interaction between two clock
+/// spins is the cosine of the angle between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSpin<const Q: usize>(usize);
+
+impl<const Q: usize> ClockSpin<Q> {
+    /// Create a clock spin in state `k`.
+    pub fn new(k: usize) -> Self {
+        ClockSpin(k % Q)
+    }
+
+    /// State of the spin.
+    pub fn state(&self) -> usize {
+        self.0
+    }
+
+    #[inline]
+    fn angle(&self) -> f64 {
+        2f64 * PI * self.0 as f64 / Q as f64
+    }
+}
+
+impl<const Q: usize> Spin for ClockSpin<Q> {
+    fn up() -> Self {
+        ClockSpin(0)
+    }
+
+    fn down() -> Self {
+        ClockSpin(Q / 2)
+    }
+
+    fn rand<R: Rng>(rng: &mut R) -> Self {
+        let range = Uniform::new(0, Q).expect("should always be able to create");
+        ClockSpin(range.sample(rng))
+    }
+
+    /// The z projection is ignored, the closest state in the plane is used.
+    fn from_projections(sx: f64, sy: f64, _sz: f64) -> Field<Self> {
+        let magnitude = sx.hypot(sy);
+        if magnitude < f64::EPSILON {
+            Field::zero()
+        } else {
+            Field::new(ClockSpin(nearest_state::<Q>(sy.atan2(sx))), magnitude)
+        }
+    }
+
+    #[inline]
+    fn dot(&self, other: &Self) -> f64 {
+        (self.angle() - other.angle()).cos()
+    }
+
+    #[inline]
+    fn sx(&self) -> f64 {
+        self.angle().cos()
+    }
+
+    #[inline]
+    fn sy(&self) -> f64 {
+        self.angle().sin()
+    }
+
+    #[inline]
+    fn sz(&self) -> f64 {
+        0.0
+    }
+
+    /// Move to the opposite state, or the mirror image for odd `Q`.
+    ///
+    /// A flip reaches a single other state, so `MetropolisFlipIntegrator`
+    /// does not sample clock spins with more than two states.
+    fn flip(&self) -> Self {
+        ClockSpin(flipped_state::<Q>(self.0))
+    }
+}
+
+/// Potts spin, a spin with `Q` equivalent states.
+///
+/// Two Potts spins interact only when they are in the same state, so `dot`
+/// is one for equal states and zero otherwise. The projections place state
+/// `k` at the angle `2πk/Q` in the plane, which makes the magnitude of the
+/// magnetization the usual Potts order parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PottsSpin<const Q: usize>(usize);
+
+impl<const Q: usize> PottsSpin<Q> {
+    /// Create a Potts spin in state `k`.
+    pub fn new(k: usize) -> Self {
+        PottsSpin(k % Q)
+    }
+
+    /// State of the spin.
+    pub fn state(&self) -> usize {
+        self.0
+    }
+
+    #[inline]
+    fn angle(&self) -> f64 {
+        2f64 * PI * self.0 as f64 / Q as f64
+    }
+}
+
+impl<const Q: usize> Spin for PottsSpin<Q> {
+    fn up() -> Self {
+        PottsSpin(0)
+    }
+
+    fn down() -> Self {
+        PottsSpin(Q / 2)
+    }
+
+    fn rand<R: Rng>(rng: &mut R) -> Self {
+        let range = Uniform::new(0, Q).expect("should always be able to create");
+        PottsSpin(range.sample(rng))
+    }
+
+    /// The z projection is ignored, the closest state in the plane is used.
+    fn from_projections(sx: f64, sy: f64, _sz: f64) -> Field<Self> {
+        let magnitude = sx.hypot(sy);
+        if magnitude < f64::EPSILON {
+            Field::zero()
+        } else {
+            Field::new(PottsSpin(nearest_state::<Q>(sy.atan2(sx))), magnitude)
+        }
+    }
+
+    #[inline]
+    fn dot(&self, other: &Self) -> f64 {
+        if self.0 == other.0 { 1f64 } else { 0f64 }
+    }
+
+    #[inline]
+    fn sx(&self) -> f64 {
+        self.angle().cos()
+    }
+
+    #[inline]
+    fn sy(&self) -> f64 {
+        self.angle().sin()
+    }
+
+    #[inline]
+    fn sz(&self) -> f64 {
+        0.0
+    }
+
+    /// Move to the state paired with this one, flipping twice gives it back.
+    ///
+    /// A flip reaches a single other state, so `MetropolisFlipIntegrator`
+    /// does not sample Potts spins with more than two states.
+    fn flip(&self) -> Self {
+        PottsSpin(flipped_state::<Q>(self.0))
+    }
+}
+
 /// Field represents a magnetic field for the given spin type.
+///
+/// The field keeps a signed strength along its orientation, so a field with a
+/// negative value points against its orientation. The orientation is a spin
+/// of full length, its projections are scaled by `Spin::length` to give the
+/// direction of the field.
 #[derive(Debug, Clone)]
 pub struct Field<S: Spin> {
     orientation: S,
@@ -215,15 +621,58 @@ impl<S: Spin> Field<S> {
         }
     }
 
+    /// Create a field along the given direction with a signed strength.
+    ///
+    /// The unit direction is projected onto the spin type and the strength
+    /// is scaled by the length of the projection, so Ising spins only see
+    /// the z component of the field and planar spins its xy components.
+    pub fn along(direction: [f64; 3], value: f64) -> Self {
+        let [x, y, z] = direction;
+        let norm = (x * x + y * y + z * z).sqrt();
+        if norm < f64::EPSILON {
+            return Field::zero();
+        }
+        let projected = S::from_projections(x / norm, y / norm, z / norm);
+        Field::new(
+            projected.orientation().clone(),
+            value * projected.magnitude(),
+        )
+    }
+
     /// Get the magnitude of the field.
     pub fn magnitude(&self) -> f64 {
         self.magnitude.abs()
     }
 
+    /// Get the signed strength of the field along its orientation.
+    pub fn value(&self) -> f64 {
+        self.magnitude
+    }
+
+    /// Projection of a spin-like vector along the orientation of the field.
+    pub fn project(&self, other: &Field<S>) -> f64 {
+        other.orientation.dot(&self.orientation) * other.magnitude / S::length().powi(2)
+    }
+
     /// Get the orientation of the field.
     pub fn orientation(&self) -> &S {
         &self.orientation
     }
+
+    /// Projection of the field along the x-axis.
+    pub fn sx(&self) -> f64 {
+        self.orientation.sx() * self.magnitude / S::length()
+    }
+
+    /// Projection of the field along the y-axis.
+    pub fn sy(&self) -> f64 {
+        self.orientation.sy() * self.magnitude / S::length()
+    }
+
+    /// Projection of the field along the z-axis.
+    pub fn sz(&self) -> f64 {
+        self.orientation.sz() * self.magnitude / S::length()
+    }
 }
 
 impl<S: Spin> Default for Field<S> {
@@ -241,6 +690,49 @@ impl<S: Spin> Sum<S> for Field<S> {
     }
 }
 
+/// Spin projections stored as one contiguous array per axis.
+///
+/// A structure of arrays copy of a state, kept up to date with `set_at`.
+/// Kernels that sweep the whole sample vectorize better over these arrays
+/// than over the spins. Kernels take the dot product of two sites as the
+/// dot product of their projections, which holds for every spin type but
+/// `PottsSpin`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpinArrays {
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+    pub z: Vec<f64>,
+}
+
+impl SpinArrays {
+    /// Copy the projections of the spins of a state.
+    pub fn from_state<S: Spin>(state: &State<S>) -> Self {
+        Self {
+            x: state.spins().iter().map(|s| s.sx()).collect(),
+            y: state.spins().iter().map(|s| s.sy()).collect(),
+            z: state.spins().iter().map(|s| s.sz()).collect(),
+        }
+    }
+
+    /// Set the projections of a site.
+    #[inline]
+    pub fn set_at<S: Spin>(&mut self, index: usize, spin: &S) {
+        self.x[index] = spin.sx();
+        self.y[index] = spin.sy();
+        self.z[index] = spin.sz();
+    }
+
+    /// Number of sites.
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    /// Check if there are no sites.
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+}
+
 /// A state of spins.
 #[derive(Clone, Debug)]
 pub struct State<S: Spin>(Vec<S>);
@@ -294,6 +786,36 @@ impl<S: Spin> State<S> {
     {
         self.spins().iter().cloned().sum()
     }
+
+    /// Get the topological charge of a state over the given triangles.
+    ///
+    /// Each counter-clockwise triangle contributes the Berg-Lüscher solid
+    /// angle spanned by its three spins, the charge is their sum over `4π`.
+    /// Spins are normalized first, so the charge doesn't depend on their
+    /// length.
+    pub fn topological_charge(&self, triangles: &[[usize; 3]]) -> f64 {
+        let length = S::length();
+        let vector = |i: usize| {
+            let spin = self.at(i);
+            [spin.sx() / length, spin.sy() / length, spin.sz() / length]
+        };
+        let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+        let solid_angle: f64 = triangles
+            .iter()
+            .map(|&[i, j, k]| {
+                let (a, b, c) = (vector(i), vector(j), vector(k));
+                let cross = [
+                    b[1] * c[2] - b[2] * c[1],
+                    b[2] * c[0] - b[0] * c[2],
+                    b[0] * c[1] - b[1] * c[0],
+                ];
+                let numerator = dot(a, cross);
+                let denominator = 1.0 + dot(a, b) + dot(b, c) + dot(c, a);
+                2.0 * numerator.atan2(denominator)
+            })
+            .sum();
+        solid_angle / (4.0 * std::f64::consts::PI)
+    }
 }
 
 impl<S> IntoIterator for State<S>
@@ -319,14 +841,31 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::state::{HeisenbergSpin, IsingSpin, Spin, State};
+    use crate::state::{
+        BlumeCapelSpin, ClockSpin, Field, HeisenbergSpin, HeisenbergSpinS, IsingSpin, IsingSpinS,
+        PottsSpin, Spin, State, XYSpin,
+    };
     use rand::SeedableRng;
     use rand_pcg::Pcg64;
+    use std::f64::consts::PI;
 
     fn assert_real_close(a: f64, b: f64) {
         assert!((a - b).abs() < 1e-15);
     }
 
+    #[test]
+    fn fields_along_a_direction_keep_the_projected_strength() {
+        let field = Field::<IsingSpin>::along([1.0, 1.0, 0.0], 2.0);
+        assert_eq!(field.value(), 0.0);
+        let field = Field::<IsingSpin>::along([1.0, 0.0, 1.0], -2.0);
+        assert!((field.sz() + 2f64.sqrt()).abs() < 1e-12);
+        let field = Field::<XYSpin>::along([0.0, 0.0, 1.0], 1.0);
+        assert_eq!(field.value(), 0.0);
+        let field = Field::<HeisenbergSpin>::along([0.0, 3.0, 4.0], 1.0);
+        assert!((field.sy() - 0.6).abs() < 1e-12);
+        assert!((field.sz() - 0.8).abs() < 1e-12);
+    }
+
     #[test]
     fn ising_spin_multiplies_correctly() {
         let up = IsingSpin::up();
@@ -349,6 +888,15 @@ mod tests {
         assert_eq!(mag.orientation(), &IsingSpin::up());
     }
 
+    #[test]
+    fn ising_magnetization_keeps_its_sign() {
+        let state = State::<IsingSpin>::down_with_size(10);
+        let mag = state.magnetization();
+        assert_real_close(mag.sx(), 0.0);
+        assert_real_close(mag.sy(), 0.0);
+        assert_real_close(mag.sz(), -10.0);
+    }
+
     #[test]
     fn heisemberg_spin_multiplies_correctly() {
         let up = HeisenbergSpin::up();
@@ -375,6 +923,92 @@ mod tests {
         }
     }
 
+    #[test]
+    fn octant_has_an_eighth_of_a_charge() {
+        let state = State(vec![
+            HeisenbergSpin([1.0, 0.0, 0.0]),
+            HeisenbergSpin([0.0, 1.0, 0.0]),
+            HeisenbergSpin([0.0, 0.0, 1.0]),
+        ]);
+        assert!((state.topological_charge(&[[0, 1, 2]]) - 0.125).abs() < 1e-12);
+        assert!((state.topological_charge(&[[0, 2, 1]]) + 0.125).abs() < 1e-12);
+        let state: State<HeisenbergSpinS<3>> = State(vec![
+            HeisenbergSpinS([1.5, 0.0, 0.0]),
+            HeisenbergSpinS([0.0, 1.5, 0.0]),
+            HeisenbergSpinS([0.0, 0.0, 1.5]),
+        ]);
+        assert!((state.topological_charge(&[[0, 1, 2]]) - 0.125).abs() < 1e-12);
+    }
+
+    #[test]
+    fn clock_and_potts_spins_interact_correctly() {
+        let a = ClockSpin::<4>::new(0);
+        let b = ClockSpin::<4>::new(1);
+        assert_real_close(a.dot(&b), (PI / 2.0).cos());
+        assert_eq!(a.flip(), ClockSpin::<4>::new(2));
+        assert_eq!(
+            ClockSpin::<4>::from_projections(0.0, -2.0, 0.0).orientation(),
+            &ClockSpin::new(3)
+        );
+        let p = PottsSpin::<3>::new(1);
+        assert_real_close(p.dot(&p), 1.0);
+        assert_real_close(p.dot(&PottsSpin::new(2)), 0.0);
+        let state: State<PottsSpin<3>> = (0..3).map(PottsSpin::new).collect();
+        assert!(state.magnetization().magnitude() < 1e-12);
+    }
+
+    #[test]
+    fn multi_state_flips_are_involutions() {
+        fn check<const Q: usize>() {
+            assert_eq!(ClockSpin::<Q>::up().flip(), ClockSpin::down());
+            assert_eq!(PottsSpin::<Q>::up().flip(), PottsSpin::down());
+            for k in 0..Q {
+                assert_eq!(ClockSpin::<Q>::new(k).flip().flip(), ClockSpin::new(k));
+                assert_eq!(PottsSpin::<Q>::new(k).flip().flip(), PottsSpin::new(k));
+            }
+        }
+        check::<2>();
+        check::<3>();
+        check::<4>();
+        check::<5>();
+        check::<7>();
+        check::<8>();
+    }
+
+    #[test]
+    fn ising_spin_s_takes_every_projection() {
+        let mut rng = Pcg64::seed_from_u64(7);
+        let mut seen: Vec<_> = (0..100)
+            .map(|_| (BlumeCapelSpin::rand(&mut rng).sz() * 2.0) as i32)
+            .collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen, vec![-2, 0, 2]);
+        assert_eq!(IsingSpinS::<3>::up().sz(), 1.5);
+        assert_eq!(IsingSpinS::<3>::new(1).flip(), IsingSpinS::new(-1));
+        let field = IsingSpinS::<3>::from_projections(0.0, 0.0, -4.5);
+        assert_eq!(field.orientation(), &IsingSpinS::down());
+        assert_real_close(field.sz(), -4.5);
+    }
+
+    #[test]
+    fn heisenberg_spin_s_has_given_length() {
+        let spin = HeisenbergSpinS::<5>::rand(&mut rand::rng());
+        assert!((spin.dot(&spin) - 6.25).abs() < 1e-12);
+        let field = HeisenbergSpinS::<5>::from_projections(3.0, 0.0, 4.0);
+        assert_real_close(field.magnitude(), 5.0);
+        assert_real_close(field.sx(), 3.0);
+        assert_real_close(field.orientation().sz(), 2.0);
+    }
+
+    #[test]
+    fn xy_spins_are_planar() {
+        let spin = XYSpin::rand(&mut rand::rng());
+        assert_real_close(spin.sz(), 0.0);
+        assert!((spin.dot(&spin) - 1.0).abs() < 1e-12);
+        assert!((spin.dot(&spin.flip()) + 1.0).abs() < 1e-12);
+    }
+
     #[test]
     fn lengths_of_states() {
         let State(items) = State::<HeisenbergSpin>::up_with_size(10);
@@ -0,0 +1,580 @@
+//! Indexed binary trajectories of the spin state.
+//!
+//! State parquet files hold three `f64` columns per spin and can only be read
+//! front to back. Trajectory files store every frame as a compact block, Ising
+//! spins as packed bits and any other spin as quantised components, and end
+//! with an index so a frame can be read back with a single seek.
+//!
+//! All integers and floats are little endian. The file holds:
+//!
+//! * A header: the magic `VEGASTRJ`, the format version (`u32`), the number of
+//!   sites (`u64`), the encoding (`u8`, 0 for bits and 1 for quantised) and
+//!   the bits per component (`u8`).
+//! * The frames: relax (`u8`), stage and step (`u64`), temperature, field
+//!   strength and field direction (`f64`), the payload length (`u64`) and the
+//!   packed spins.
+//! * The index: the number of frames (`u64`) followed by the stage, step,
+//!   relax flag and offset of each of them.
+//! * The metadata: the number of entries (`u64`) followed by length prefixed
+//!   keys and values.
+//! * A footer: the offset of the index (`u64`) and the magic `VEGASIDX`.
+//!
+//! The index is written when the writer is dropped. Files from runs that
+//! never finished lack it, the reader then rebuilds it by walking the frames.
+
+use crate::{
+    error::{IoError, IoResult},
+    output::{Metadata, Snapshot, WithMetadata},
+    state::{Field, Spin, State},
+    thermostat::Thermostat,
+};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::Path,
+};
+
+const MAGIC: &[u8; 8] = b"VEGASTRJ";
+const INDEX_MAGIC: &[u8; 8] = b"VEGASIDX";
+const VERSION: u32 = 1;
+const HEADER_LEN: u64 = 8 + 4 + 8 + 1 + 1;
+const FRAME_HEADER_LEN: u64 = 1 + 8 + 8 + 8 + 8 + 3 * 8 + 8;
+
+/// How the spins of a frame are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrajectoryEncoding {
+    /// One bit per spin, set when the spin points up along z
+    Bits,
+    /// Every component of the spin direction rounded to the given number of
+    /// bits, from 1 to 32
+    Quantized(u8),
+}
+
+impl TrajectoryEncoding {
+    /// Quantise spins to the given number of bits per component.
+    pub fn quantized(bits: u8) -> IoResult<Self> {
+        if (1..=32).contains(&bits) {
+            Ok(TrajectoryEncoding::Quantized(bits))
+        } else {
+            Err(IoError::UnsupportedPrecision(bits))
+        }
+    }
+
+    fn tag(&self) -> (u8, u8) {
+        match self {
+            TrajectoryEncoding::Bits => (0, 1),
+            TrajectoryEncoding::Quantized(bits) => (1, *bits),
+        }
+    }
+
+    fn from_tag(tag: u8, bits: u8) -> IoResult<Self> {
+        match tag {
+            0 => Ok(TrajectoryEncoding::Bits),
+            1 => TrajectoryEncoding::quantized(bits),
+            _ => Err(IoError::BadTrajectory(format!("unknown encoding {}", tag))),
+        }
+    }
+
+    /// Number of bytes taken by the spins of a frame.
+    fn payload_len(&self, sites: usize) -> usize {
+        let bits = match self {
+            TrajectoryEncoding::Bits => sites,
+            TrajectoryEncoding::Quantized(bits) => 3 * sites * *bits as usize,
+        };
+        bits.div_ceil(8)
+    }
+
+    fn encode<S: Spin>(&self, state: &State<S>) -> Vec<u8> {
+        let mut packer = BitPacker::with_capacity(self.payload_len(state.len()));
+        match self {
+            TrajectoryEncoding::Bits => {
+                for spin in state.spins() {
+                    packer.push(u64::from(spin.sz() > 0.0), 1);
+                }
+            }
+            TrajectoryEncoding::Quantized(bits) => {
+                let levels = ((1u64 << bits) - 1) as f64;
+                for spin in state.spins() {
+                    for component in [spin.sx(), spin.sy(), spin.sz()] {
+                        let unit = (component / S::length()).clamp(-1.0, 1.0);
+                        packer.push(((unit + 1.0) / 2.0 * levels).round() as u64, *bits);
+                    }
+                }
+            }
+        }
+        packer.finish()
+    }
+
+    fn decode<S: Spin>(&self, payload: &[u8], sites: usize) -> State<S> {
+        let mut unpacker = BitUnpacker::new(payload);
+        match self {
+            TrajectoryEncoding::Bits => (0..sites)
+                .map(|_| {
+                    let sign = if unpacker.pop(1) == 1 { 1.0 } else { -1.0 };
+                    S::closest(0.0, 0.0, sign * S::length())
+                })
+                .collect(),
+            TrajectoryEncoding::Quantized(bits) => {
+                let levels = ((1u64 << bits) - 1) as f64;
+                (0..sites)
+                    .map(|_| {
+                        let [sx, sy, sz] = [(); 3].map(|_| {
+                            let unit = unpacker.pop(*bits) as f64 / levels * 2.0 - 1.0;
+                            unit * S::length()
+                        });
+                        S::closest(sx, sy, sz)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Packs unsigned values of up to 32 bits into bytes, least significant
+/// bit first.
+struct BitPacker {
+    bytes: Vec<u8>,
+    buffer: u64,
+    len: u8,
+}
+
+impl BitPacker {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            bytes: Vec::with_capacity(capacity),
+            buffer: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, value: u64, bits: u8) {
+        self.buffer |= (value & ((1 << bits) - 1)) << self.len;
+        self.len += bits;
+        while self.len >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Reads back the values packed by `BitPacker`.
+struct BitUnpacker<'a> {
+    bytes: std::slice::Iter<'a, u8>,
+    buffer: u64,
+    len: u8,
+}
+
+impl<'a> BitUnpacker<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes: bytes.iter(),
+            buffer: 0,
+            len: 0,
+        }
+    }
+
+    fn pop(&mut self, bits: u8) -> u64 {
+        while self.len < bits {
+            let byte = self.bytes.next().copied().unwrap_or_default();
+            self.buffer |= u64::from(byte) << self.len;
+            self.len += 8;
+        }
+        let value = self.buffer & ((1 << bits) - 1);
+        self.buffer >>= bits;
+        self.len -= bits;
+        value
+    }
+}
+
+/// Entry of the index of a trajectory file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameEntry {
+    /// Whether the frame was taken while relaxing
+    pub relax: bool,
+    /// Stage of the frame
+    pub stage: usize,
+    /// Step of the frame within its stage
+    pub step: usize,
+    offset: u64,
+}
+
+/// Writes frames of the spin state to a trajectory file.
+pub struct TrajectoryWriter {
+    writer: Option<BufWriter<File>>,
+    sites: usize,
+    encoding: TrajectoryEncoding,
+    offset: u64,
+    index: Vec<FrameEntry>,
+    metadata: Metadata,
+}
+
+impl TrajectoryWriter {
+    pub fn try_new<P: AsRef<Path>>(
+        path: P,
+        sites: usize,
+        encoding: TrajectoryEncoding,
+    ) -> IoResult<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let (tag, bits) = encoding.tag();
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(sites as u64).to_le_bytes())?;
+        writer.write_all(&[tag, bits])?;
+        Ok(Self {
+            writer: Some(writer),
+            sites,
+            encoding,
+            offset: HEADER_LEN,
+            index: Vec::new(),
+            metadata: Metadata::new(),
+        })
+    }
+
+    /// Append a frame with the given state.
+    pub fn write<S: Spin>(
+        &mut self,
+        relax: bool,
+        stage: usize,
+        step: usize,
+        thermostat: &Thermostat<S>,
+        state: &State<S>,
+    ) -> IoResult<()> {
+        debug_assert!(state.len() == self.sites);
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        let orientation = thermostat.field().orientation();
+        let payload = self.encoding.encode(state);
+        writer.write_all(&[u8::from(relax)])?;
+        writer.write_all(&(stage as u64).to_le_bytes())?;
+        writer.write_all(&(step as u64).to_le_bytes())?;
+        for value in [
+            thermostat.temperature(),
+            thermostat.field().value(),
+            orientation.sx() / S::length(),
+            orientation.sy() / S::length(),
+            orientation.sz() / S::length(),
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&(payload.len() as u64).to_le_bytes())?;
+        writer.write_all(&payload)?;
+        self.index.push(FrameEntry {
+            relax,
+            stage,
+            step,
+            offset: self.offset,
+        });
+        self.offset += FRAME_HEADER_LEN + payload.len() as u64;
+        Ok(())
+    }
+
+    fn finish(&mut self, mut writer: BufWriter<File>) -> IoResult<()> {
+        writer.write_all(&(self.index.len() as u64).to_le_bytes())?;
+        for entry in &self.index {
+            writer.write_all(&(entry.stage as u64).to_le_bytes())?;
+            writer.write_all(&(entry.step as u64).to_le_bytes())?;
+            writer.write_all(&[u8::from(entry.relax)])?;
+            writer.write_all(&entry.offset.to_le_bytes())?;
+        }
+        let entries: Vec<_> = self.metadata.iter().collect();
+        writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for (key, value) in entries {
+            for text in [key, value] {
+                writer.write_all(&(text.len() as u64).to_le_bytes())?;
+                writer.write_all(text.as_bytes())?;
+            }
+        }
+        writer.write_all(&self.offset.to_le_bytes())?;
+        writer.write_all(INDEX_MAGIC)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The metadata is written after the index.
+impl WithMetadata for TrajectoryWriter {
+    fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+}
+
+impl Drop for TrajectoryWriter {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take()
+            && let Err(err) = self.finish(writer)
+        {
+            eprintln!("error writing trajectory index: {}", err);
+        }
+    }
+}
+
+/// Reads frames of a trajectory file in any order.
+///
+/// Spins are rebuilt with `Spin::closest`, so quantised spins come back
+/// normalized and discrete spins snap to their nearest state.
+pub struct TrajectoryReader<S: Spin> {
+    reader: BufReader<File>,
+    sites: usize,
+    encoding: TrajectoryEncoding,
+    frames: Vec<FrameEntry>,
+    metadata: Metadata,
+    phantom: PhantomData<S>,
+}
+
+impl<S: Spin> TrajectoryReader<S> {
+    pub fn try_new<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        if &read_array::<_, 8>(&mut reader)? != MAGIC {
+            return Err(IoError::BadTrajectory("not a trajectory file".into()));
+        }
+        let version = u32::from_le_bytes(read_array(&mut reader)?);
+        if version != VERSION {
+            return Err(IoError::BadTrajectory(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let sites = read_u64(&mut reader)? as usize;
+        let [tag, bits] = read_array(&mut reader)?;
+        let encoding = TrajectoryEncoding::from_tag(tag, bits)?;
+        let (frames, metadata) = match read_index(&mut reader)? {
+            Some(index) => index,
+            None => {
+                let payload = encoding.payload_len(sites) as u64;
+                (scan_frames(&mut reader, payload)?, Metadata::new())
+            }
+        };
+        Ok(Self {
+            reader,
+            sites,
+            encoding,
+            frames,
+            metadata,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Number of sites of every frame.
+    pub fn sites(&self) -> usize {
+        self.sites
+    }
+
+    /// Encoding of the spins.
+    pub fn encoding(&self) -> TrajectoryEncoding {
+        self.encoding
+    }
+
+    /// Index of the frames in the order they were written.
+    pub fn frames(&self) -> &[FrameEntry] {
+        &self.frames
+    }
+
+    /// Metadata embedded in the file, empty if the index is missing.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Read the frame at the given position of the index.
+    pub fn read(&mut self, frame: usize) -> IoResult<Option<Snapshot<S>>> {
+        let Some(entry) = self.frames.get(frame).copied() else {
+            return Ok(None);
+        };
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        let [relax] = read_array(&mut self.reader)?;
+        let stage = read_u64(&mut self.reader)? as usize;
+        let step = read_u64(&mut self.reader)? as usize;
+        let [temperature, value, x, y, z] =
+            [(); 5].map(|_| read_array(&mut self.reader).map(f64::from_le_bytes));
+        let mut payload = vec![0; read_u64(&mut self.reader)? as usize];
+        self.reader.read_exact(&mut payload)?;
+        if payload.len() != self.encoding.payload_len(self.sites) {
+            return Err(IoError::BadTrajectory(format!(
+                "frame {} has {} bytes of spins",
+                frame,
+                payload.len()
+            )));
+        }
+        let field = Field::along([x?, y?, z?], value?);
+        Ok(Some(Snapshot {
+            relax: relax != 0,
+            stage,
+            step,
+            thermostat: Thermostat::new(temperature?, field),
+            state: self.encoding.decode(&payload, self.sites),
+        }))
+    }
+
+    /// Find the last frame matching the given stage and step.
+    ///
+    /// Either of them matches any frame when omitted, so leaving both out
+    /// gives the last frame of the file. Only the matching frame is read.
+    pub fn find(
+        &mut self,
+        stage: Option<usize>,
+        step: Option<usize>,
+    ) -> IoResult<Option<Snapshot<S>>> {
+        let found = self.frames.iter().rposition(|entry| {
+            stage.is_none_or(|s| s == entry.stage) && step.is_none_or(|s| s == entry.step)
+        });
+        match found {
+            Some(frame) => self.read(frame),
+            None => Ok(None),
+        }
+    }
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> IoResult<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> IoResult<u64> {
+    Ok(u64::from_le_bytes(read_array(reader)?))
+}
+
+/// Read the index and metadata through the footer, if there is one.
+fn read_index<R: Read + Seek>(reader: &mut R) -> IoResult<Option<(Vec<FrameEntry>, Metadata)>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    if len < HEADER_LEN + 16 {
+        return Ok(None);
+    }
+    reader.seek(SeekFrom::End(-16))?;
+    let offset = read_u64(reader)?;
+    if &read_array::<_, 8>(reader)? != INDEX_MAGIC {
+        return Ok(None);
+    }
+    reader.seek(SeekFrom::Start(offset))?;
+    let count = read_u64(reader)?;
+    let mut frames = Vec::new();
+    for _ in 0..count {
+        let stage = read_u64(reader)? as usize;
+        let step = read_u64(reader)? as usize;
+        let [relax] = read_array(reader)?;
+        frames.push(FrameEntry {
+            relax: relax != 0,
+            stage,
+            step,
+            offset: read_u64(reader)?,
+        });
+    }
+    let mut metadata = Metadata::new();
+    for _ in 0..read_u64(reader)? {
+        let [key, value] = [(); 2].map(|_| read_string(reader));
+        metadata = metadata.set(key?, value?);
+    }
+    Ok(Some((frames, metadata)))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> IoResult<String> {
+    let mut bytes = vec![0; read_u64(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|err| IoError::BadTrajectory(err.to_string()))
+}
+
+/// Rebuild the index by walking the frames, stopping at the first one that
+/// is truncated or whose payload does not have the expected length.
+fn scan_frames<R: Read + Seek>(reader: &mut R, payload_len: u64) -> IoResult<Vec<FrameEntry>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut offset = reader.seek(SeekFrom::Start(HEADER_LEN))?;
+    let mut frames = Vec::new();
+    while offset + FRAME_HEADER_LEN <= len {
+        let [relax] = read_array(reader)?;
+        let stage = read_u64(reader)? as usize;
+        let step = read_u64(reader)? as usize;
+        reader.seek(SeekFrom::Current(5 * 8))?;
+        let payload = read_u64(reader)?;
+        let next = offset + FRAME_HEADER_LEN + payload;
+        if relax > 1 || payload != payload_len || next > len {
+            break;
+        }
+        frames.push(FrameEntry {
+            relax: relax != 0,
+            stage,
+            step,
+            offset,
+        });
+        offset = reader.seek(SeekFrom::Start(next))?;
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{HeisenbergSpin, IsingSpin};
+    use rand::SeedableRng;
+    use rand_pcg::Pcg64;
+
+    #[test]
+    fn frames_are_read_back_by_stage_and_step() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trajectory.trj");
+        let mut rng = Pcg64::seed_from_u64(42);
+        let states: Vec<State<IsingSpin>> = (0..4)
+            .map(|_| State::rand_with_size(&mut rng, 13))
+            .collect();
+        let thermostat = Thermostat::new(2.0, Field::along([0.0, 0.0, 1.0], 0.5));
+        {
+            let mut writer =
+                TrajectoryWriter::try_new(&path, 13, TrajectoryEncoding::Bits).unwrap();
+            writer.set_metadata(&Metadata::new().set("vegas.seed", 42));
+            for (i, state) in states.iter().enumerate() {
+                writer
+                    .write(i < 2, i / 2, 10 * i, &thermostat, state)
+                    .unwrap();
+            }
+        }
+        let mut reader = TrajectoryReader::<IsingSpin>::try_new(&path).unwrap();
+        assert_eq!(reader.frames().len(), 4);
+        assert_eq!(reader.metadata().get("vegas.seed"), Some("42"));
+        let snapshot = reader.find(Some(1), Some(20)).unwrap().unwrap();
+        assert!(!snapshot.relax);
+        assert_eq!(snapshot.state.spins(), states[2].spins());
+        assert_eq!(snapshot.thermostat.field().value(), 0.5);
+        assert!(reader.find(Some(2), None).unwrap().is_none());
+
+        // Without the index the frames are found by walking the file.
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 20).unwrap();
+        let mut reader = TrajectoryReader::<IsingSpin>::try_new(&path).unwrap();
+        assert_eq!(reader.frames().len(), 4);
+        let snapshot = reader.find(None, None).unwrap().unwrap();
+        assert_eq!(snapshot.state.spins(), states[3].spins());
+    }
+
+    #[test]
+    fn quantized_spins_stay_within_precision() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trajectory.trj");
+        let mut rng = Pcg64::seed_from_u64(42);
+        let state = State::<HeisenbergSpin>::rand_with_size(&mut rng, 100);
+        {
+            let encoding = TrajectoryEncoding::quantized(12).unwrap();
+            let mut writer = TrajectoryWriter::try_new(&path, 100, encoding).unwrap();
+            writer
+                .write(false, 0, 0, &Thermostat::near_zero(), &state)
+                .unwrap();
+        }
+        let size = std::fs::metadata(&path).unwrap().len();
+        let mut reader = TrajectoryReader::<HeisenbergSpin>::try_new(&path).unwrap();
+        let snapshot = reader.read(0).unwrap().unwrap();
+        assert!(size < 100 * 3 * 8);
+        for (a, b) in state.spins().iter().zip(snapshot.state.spins()) {
+            assert!(a.dot(b) > 1.0 - 1e-5);
+        }
+        assert!(TrajectoryEncoding::quantized(0).is_err());
+        assert!(TrajectoryEncoding::quantized(33).is_err());
+    }
+}